@@ -7,16 +7,17 @@ make_server!();
 async fn main() {
     let server = WebServer::new(8080);
 
-    SocketIO::add_ns("/");
+    let socket_io = server.socket_io();
+    socket_io.add_ns("/");
     std::thread::spawn(move || {
         let mut i = 0;
 
         loop {
             i += 1;
-            SocketIO::emit("/", "message", json!(format!("Counter: {i}")));
+            socket_io.emit("/", "message", json!(format!("Counter: {i}")));
             std::thread::sleep(std::time::Duration::from_secs(2))
         }
     });
 
-    server.start().await;
+    server.start().await.unwrap();
 }
@@ -0,0 +1,10 @@
+use crate::{Next, Request, Response};
+
+pub async fn middleware<'a>(req: Request<'a>, next: Next<'a>) -> Result<Response, Response> {
+    let mut response = next.run(req).await;
+
+    let (Ok(r) | Err(r)) = &mut response;
+    r.headers.insert("X-Api-Version", "1")?;
+
+    response
+}
@@ -0,0 +1,12 @@
+use crate::{Request, Response, SseEvent};
+use futures::StreamExt;
+use std::time::Duration;
+
+pub async fn get<'a>(_: Request<'a>) -> Result<Response, Response> {
+    let events = futures::stream::iter(1..=5).then(|i| async move {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        SseEvent::new(format!("{}%", i * 20)).id(i).event("progress")
+    });
+
+    Ok(Response::sse(events, Some(Duration::from_secs(15))))
+}
@@ -1,7 +1,18 @@
-use crate::{json_response, ReqMethod, Request, Response};
+use crate::{json_response, Request, Response};
+use serde::Deserialize;
 
-pub async fn handler<'a>(req: Request<'a>) -> Result<Response, Response> {
-    req.allow_methods(vec![ReqMethod::Get])?;
+#[derive(Deserialize)]
+pub struct Detail {
+    name: String,
+    tags: Vec<String>,
+}
 
+pub async fn get<'a>(_: Request<'a>) -> Result<Response, Response> {
     Ok(json_response!(200, {"message": "Welcome"}))
 }
+
+pub async fn post<'a>(mut req: Request<'a>) -> Result<Response, Response> {
+    let detail: Detail = req.json().await?;
+
+    Ok(json_response!(201, {"name": detail.name, "tags": detail.tags}))
+}
@@ -1,11 +1,19 @@
-use crate::{ReqMethod, Request, Response};
+use crate::{Json, Request, Response};
+use serde::Serialize;
 
-pub async fn handler<'a>(req: Request<'a>) -> Result<Response, Response> {
-    req.allow_methods(vec![ReqMethod::Get])?;
+#[derive(Serialize)]
+pub struct Item {
+    id: String,
+    path: String,
+}
+
+pub async fn get<'a>(req: Request<'a>) -> Result<Json<Item>, Response> {
+    let id = req
+        .dyn_fields
+        .as_ref()
+        .and_then(|f| f.get("id"))
+        .map(|id| id.to_string())
+        .ok_or_else(|| Response::bad_request().text("Missing id"))?;
 
-    Ok(Response::from_string(
-        200,
-        None,
-        Some(&format!("Hi from {}", req.path)),
-    ))
+    Ok(Json(Item { id, path: req.path }))
 }
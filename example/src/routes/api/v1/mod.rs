@@ -1,8 +1,6 @@
-use crate::{ReqMethod, Request, Response};
-
-pub async fn handler<'a>(req: Request<'a>) -> Result<Response, Response> {
-    req.allow_methods(vec![ReqMethod::Get])?;
+use crate::{Request, Response};
 
+pub async fn get<'a>(req: Request<'a>) -> Result<Response, Response> {
     Ok(Response::from_string(
         200,
         None,
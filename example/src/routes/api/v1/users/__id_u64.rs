@@ -0,0 +1,7 @@
+use crate::{json_response, Request, Response};
+
+pub type Params = u64;
+
+pub async fn get<'a>(_: Request<'a>, id: Params) -> Result<Response, Response> {
+    Ok(json_response!(200, { "id": id }))
+}
@@ -0,0 +1,11 @@
+use crate::{json_response, Request, Response};
+
+pub async fn get<'a>(req: Request<'a>) -> Result<Response, Response> {
+    let path = req
+        .dyn_fields
+        .as_ref()
+        .and_then(|f| f.get("path"))
+        .map_or(&[][..], |p| p.as_list());
+
+    Ok(json_response!(200, { "path": path }))
+}
@@ -0,0 +1,197 @@
+use proc_macro2::Span;
+use std::collections::HashSet;
+use syn::{
+    parse::{Parse, ParseStream},
+    Error, Ident, LitBool, LitInt, LitStr, Token,
+};
+
+static ARGUMENTS: [&str; 5] = ["routes", "socket_path", "cors", "static_max_bytes", "tls"];
+
+/// `make_server!` arguments.
+///
+/// Arguments are given as `key = value` pairs, e.g.
+/// `make_server!(routes = "src/web", socket_path = "/rt", cors = false)`.
+/// A leading string literal is a shorthand for the `routes` argument.
+pub struct Config {
+    /// Path of the `routes` directory, relative to the crate root
+    pub routes: Option<LitStr>,
+
+    /// Path prefix of the `socket.io` requests
+    pub socket_path: String,
+
+    /// Whether the permissive CORS policy is applied by default
+    pub cors: bool,
+
+    /// Maximum size of the static files embedded in the binary
+    pub static_max_bytes: Option<LitInt>,
+
+    /// Whether the HTTPS support (depending on `tokio-rustls`) is generated
+    pub tls: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            routes: None,
+            socket_path: String::from("/socket.io"),
+            cors: true,
+            static_max_bytes: None,
+            tls: false,
+        }
+    }
+}
+
+impl Config {
+    /// Span of the `routes` argument (if any), to point the route scanning errors at
+    pub fn span(&self) -> Span {
+        self.routes
+            .as_ref()
+            .map_or_else(Span::call_site, |routes| routes.span())
+    }
+
+    pub fn static_max_bytes(&self) -> Option<u64> {
+        self.static_max_bytes
+            .as_ref()
+            .and_then(|l| l.base10_parse().ok())
+    }
+}
+
+impl Parse for Config {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut config = Config::default();
+        let mut parsed = HashSet::<String>::new();
+
+        if input.peek(LitStr) {
+            config.routes = Some(input.parse()?);
+            parsed.insert(String::from("routes"));
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+            let name = key.to_string();
+            input.parse::<Token![=]>()?;
+
+            if !ARGUMENTS.contains(&name.as_str()) {
+                return Err(Error::new(
+                    key.span(),
+                    format!(
+                        "Unknown argument `{name}`, expected one of: {}",
+                        ARGUMENTS.join(", ")
+                    ),
+                ));
+            }
+
+            if !parsed.insert(name.clone()) {
+                return Err(Error::new(
+                    key.span(),
+                    format!("Argument `{name}` specified more than once"),
+                ));
+            }
+
+            match name.as_str() {
+                "routes" => config.routes = Some(input.parse()?),
+                "socket_path" => {
+                    let socket_path: LitStr = input.parse()?;
+
+                    if !socket_path.value().starts_with('/') {
+                        return Err(Error::new(
+                            socket_path.span(),
+                            "`socket_path` must start with '/'",
+                        ));
+                    }
+
+                    config.socket_path = socket_path.value();
+                }
+                "cors" => config.cors = input.parse::<LitBool>()?.value,
+                "tls" => config.tls = input.parse::<LitBool>()?.value,
+                _ => {
+                    let static_max_bytes: LitInt = input.parse()?;
+                    static_max_bytes.base10_parse::<u64>()?;
+                    config.static_max_bytes = Some(static_max_bytes);
+                }
+            }
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse the `input` arguments, checking that the error expands to a `compile_error!`
+    /// and returning its message and the column it points at
+    fn error(input: &str) -> (String, usize) {
+        let error = syn::parse_str::<Config>(input).err().unwrap();
+        let message = error.to_string();
+        assert_eq!(
+            error.to_compile_error().to_string(),
+            format!(":: core :: compile_error ! {{ {message:?} }}")
+        );
+
+        (message, error.span().start().column)
+    }
+
+    #[test]
+    fn leading_literal_and_routes_argument() {
+        let config = syn::parse_str::<Config>(r#""src/web", cors = false"#).unwrap();
+        assert_eq!(config.routes.unwrap().value(), "src/web");
+        assert!(!config.cors);
+
+        let config = syn::parse_str::<Config>(r#"routes = "src/web""#).unwrap();
+        assert_eq!(config.routes.unwrap().value(), "src/web");
+
+        let (message, column) = error(r#""src/web", routes = "src/app""#);
+        assert_eq!(message, "Argument `routes` specified more than once");
+        assert_eq!(column, 11);
+    }
+
+    #[test]
+    fn unknown_argument() {
+        let (message, column) = error("cors = true, port = 80");
+        assert_eq!(
+            message,
+            "Unknown argument `port`, expected one of: routes, socket_path, cors, static_max_bytes, tls"
+        );
+        assert_eq!(column, 13);
+    }
+
+    #[test]
+    fn duplicate_argument() {
+        let (message, column) = error("cors = true, cors = false");
+        assert_eq!(message, "Argument `cors` specified more than once");
+        assert_eq!(column, 13);
+    }
+
+    #[test]
+    fn static_max_bytes_type() {
+        let config = syn::parse_str::<Config>("static_max_bytes = 1024").unwrap();
+        assert_eq!(config.static_max_bytes(), Some(1024));
+
+        let (message, column) = error(r#"static_max_bytes = "1 KB""#);
+        assert_eq!(message, "expected integer literal");
+        assert_eq!(column, 19);
+
+        let (_, column) = error("static_max_bytes = -1");
+        assert_eq!(column, 19);
+    }
+
+    #[test]
+    fn tls() {
+        assert!(!syn::parse_str::<Config>("").unwrap().tls);
+        assert!(syn::parse_str::<Config>("tls = true").unwrap().tls);
+
+        let (message, column) = error(r#"tls = "yes""#);
+        assert_eq!(message, "expected boolean literal");
+        assert_eq!(column, 6);
+    }
+}
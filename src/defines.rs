@@ -1,516 +1,3273 @@
-pub static DEFINES: &str = stringify! { // <=
-$modules // <=
-
-use engineioxide::service::NotFoundService;
-use futures::{executor, StreamExt};
-use http_body_util::{BodyExt, BodyStream, Full};
-use hyper::{
-    body::{Bytes, Incoming},
-    header::{HeaderValue, CONTENT_TYPE},
-    server::conn::http1,
-    service::{service_fn, Service},
-    Request as HyperRequest, Response as HyperResponse,
-};
-use hyper_util::rt::TokioIo;
-use lazy_static::lazy_static;
-use multer::Multipart;
-use regex::Regex;
-use serde_json::Value;
-use socketioxide::{
-    adapter::LocalAdapter,
-    extract::{Data, SocketRef},
-    service::SocketIoService,
-    socket::DisconnectReason,
-    SocketIo,
-};
-use std::{
-    collections::HashMap,
-    convert::Infallible,
-    fmt::Display,
-    fs::{metadata, File},
-    io::Write,
-    net::SocketAddr,
-    path::Path,
-    str::FromStr,
-    sync::Mutex,
-};
-use tokio::net::TcpListener;
-
-type SocketIOService = SocketIoService<NotFoundService, LocalAdapter>;
-
-lazy_static! {
-    static ref SOCKET_SERVICE: (Mutex<SocketIOService>, SocketIo) = {
-        let (service, io) = SocketIo::new_svc();
-        (Mutex::new(service), io)
-    };
-    static ref SOCKETS: Mutex<HashMap<String, SocketRef>> = Mutex::new(HashMap::new());
-    static ref DYN_FIELDS_REGEX: Regex = Regex::new(r"__(?P<field>[\w\-_]+)").unwrap();
-}
+use super::config::Config;
+use proc_macro2::TokenStream;
+use quote::quote;
+
+/// Generate the server components, wrapping the route `modules`, the `router`
+/// trie, the `handlers` of the matched routes and the `error_pages` renderer.
+///
+/// The internals are defined in a private module, only the public
+/// components are exported at the call site.
+pub fn defines(
+    modules: TokenStream,
+    router: TokenStream,
+    handlers: Vec<TokenStream>,
+    error_pages: TokenStream,
+    config: &Config,
+) -> TokenStream {
+    let socket_path = &config.socket_path;
+    let cors = config.cors;
+    let Tls {
+        items: tls_items,
+        field: tls_field,
+        init: tls_init,
+        setup: tls_setup,
+        serve,
+        exports: tls_exports,
+    } = Tls::new(config);
+
+    quote! {
+        #modules
+
+        #[doc(hidden)]
+        #[allow(dead_code)]
+        mod __next_rs {
+            use engineioxide::service::NotFoundService;
+            use futures::{future, stream, FutureExt, Stream, StreamExt};
+            use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Full, StreamBody};
+            use hyper::{
+                body::{Body as _, Bytes, Frame, Incoming},
+                header::{
+                    HeaderName, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+                    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+                    ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+                    ACCESS_CONTROL_REQUEST_METHOD, ALLOW, CONTENT_LENGTH, CONTENT_TYPE, ORIGIN, VARY,
+                },
+                service::{service_fn, Service},
+                HeaderMap, Method, Request as HyperRequest, Response as HyperResponse, StatusCode, Uri,
+            };
+            use hyper_util::{
+                rt::{TokioExecutor, TokioIo, TokioTimer},
+                server::conn::auto,
+            };
+            use multer::Multipart;
+            use serde::{de::DeserializeOwned, Serialize};
+            use serde_json::Value;
+            use socketioxide::{
+                adapter::LocalAdapter,
+                extract::{Data, SocketRef},
+                service::SocketIoService,
+                socket::DisconnectReason,
+                SocketIo,
+            };
+            use std::{
+                any::Any,
+                collections::HashMap,
+                convert::Infallible,
+                fmt::Display,
+                fs::metadata,
+                future::Future,
+                io,
+                net::SocketAddr,
+                panic::AssertUnwindSafe,
+                path::{Path, PathBuf},
+                pin::Pin,
+                str::FromStr,
+                sync::{
+                    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+                    Arc, Mutex,
+                },
+                task::{Context, Poll},
+                time::{Duration, SystemTime, UNIX_EPOCH},
+            };
+            use tokio::{
+                io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+                net::TcpListener,
+                sync::{watch, OwnedSemaphorePermit, Semaphore},
+                time::{Instant, MissedTickBehavior},
+            };
+
+            type SocketIOService = SocketIoService<NotFoundService, LocalAdapter>;
+
+            /// Body of the hyper responses
+            type HttpBody = UnsyncBoxBody<Bytes, io::Error>;
+
+            fn full_body(bytes: Bytes) -> HttpBody {
+                Full::new(bytes).map_err(|e| match e {}).boxed_unsync()
+            }
+
+            /// Path prefix of the `socket.io` requests
+            const SOCKET_IO_PATH: &str = #socket_path;
+
+            /// Whether the permissive CORS policy is applied by default
+            const CORS_ENABLED: bool = #cors;
+
+            /// Header carrying the id of a request whose handler panicked
+            const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+            /// HTTP request method
+            #[derive(Debug, Clone, PartialEq)]
+            pub enum ReqMethod {
+                Get,
+                Patch,
+                Post,
+                Put,
+                Delete,
+                Options,
+                Head,
+                Trace,
+                Connect,
+
+                /// A method not defined by the HTTP specification (e.g. `PROPFIND`).
+                /// Route modules exporting per-method handlers answer it with
+                /// `501 Not Implemented`.
+                Other(String),
+            }
+
+            impl ReqMethod {
+                pub fn from(string: &str) -> ReqMethod {
+                    match string {
+                        "GET" => ReqMethod::Get,
+                        "PATCH" => ReqMethod::Patch,
+                        "POST" => ReqMethod::Post,
+                        "PUT" => ReqMethod::Put,
+                        "DELETE" => ReqMethod::Delete,
+                        "OPTIONS" => ReqMethod::Options,
+                        "HEAD" => ReqMethod::Head,
+                        "TRACE" => ReqMethod::Trace,
+                        "CONNECT" => ReqMethod::Connect,
+                        other => ReqMethod::Other(other.to_string()),
+                    }
+                }
+            }
+
+            impl Display for ReqMethod {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    write!(
+                        f,
+                        "{}",
+                        match self {
+                            ReqMethod::Get => "GET",
+                            ReqMethod::Patch => "PATCH",
+                            ReqMethod::Post => "POST",
+                            ReqMethod::Put => "PUT",
+                            ReqMethod::Delete => "DELETE",
+                            ReqMethod::Options => "OPTIONS",
+                            ReqMethod::Head => "HEAD",
+                            ReqMethod::Trace => "TRACE",
+                            ReqMethod::Connect => "CONNECT",
+                            ReqMethod::Other(method) => method,
+                        }
+                    )
+                }
+            }
+
+            /// Value of a dynamic route segment
+            #[derive(Debug, Clone, PartialEq)]
+            pub enum DynField {
+                /// Path segment matched by a `__name` route segment
+                Segment(String),
+
+                /// Path segments matched by a `___name` (catch-all) or
+                /// `____name` (optional catch-all) route segment
+                CatchAll(Vec<String>),
+            }
+
+            impl DynField {
+                /// Get the value of a `__name` segment
+                pub fn as_str(&self) -> Option<&str> {
+                    match self {
+                        DynField::Segment(value) => Some(value),
+                        DynField::CatchAll(_) => None,
+                    }
+                }
+
+                /// Get the values of the segment, a single one for `__name` segments
+                pub fn as_list(&self) -> &[String] {
+                    match self {
+                        DynField::Segment(value) => std::slice::from_ref(value),
+                        DynField::CatchAll(values) => values,
+                    }
+                }
+            }
+
+            impl Display for DynField {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    write!(f, "{}", self.as_list().join("/"))
+                }
+            }
+
+            /// An error reading a request `Body`
+            #[derive(Debug)]
+            pub enum BodyError {
+                /// The body exceeds the maximum size (in bytes) accepted by the server
+                TooLarge(u64),
+                Read(hyper::Error),
+            }
+
+            impl Display for BodyError {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    match self {
+                        BodyError::TooLarge(max) => write!(f, "Payload too large (max {max} bytes)"),
+                        BodyError::Read(e) => write!(f, "Cannot read the request body: {e}"),
+                    }
+                }
+            }
+
+            impl std::error::Error for BodyError {}
+
+            impl From<BodyError> for Response {
+                fn from(error: BodyError) -> Self {
+                    let code = match error {
+                        BodyError::TooLarge(_) => 413,
+                        BodyError::Read(_) => 400,
+                    };
+
+                    json_response!(code, {"message": error.to_string()})
+                }
+            }
+
+            /// The body of a request, streamed from the connection as a
+            /// `Stream` of byte chunks (or read at once with `bytes`, `text`
+            /// or `json`). Reading more than the maximum body size of the
+            /// server fails with `BodyError::TooLarge`.
+            #[derive(Debug)]
+            pub struct Body {
+                incoming: Option<Incoming>,
+                content_type: Option<String>,
+                max_size: Option<u64>,
+                read: u64,
+            }
+
+            impl Body {
+                fn new(incoming: Incoming, content_type: Option<String>, max_size: Option<u64>) -> Self {
+                    Body {
+                        incoming: Some(incoming),
+                        content_type,
+                        max_size,
+                        read: 0,
+                    }
+                }
+
+                /// An empty body (e.g. the one of a multipart request, read
+                /// through `Request::multipart_body` instead)
+                pub fn empty() -> Self {
+                    Body {
+                        incoming: None,
+                        content_type: None,
+                        max_size: None,
+                        read: 0,
+                    }
+                }
+
+                /// Read the next chunk of the body (`None` at its end)
+                pub async fn chunk(&mut self) -> Result<Option<Bytes>, BodyError> {
+                    self.next().await.transpose()
+                }
+
+                /// Read the rest of the body
+                pub async fn bytes(&mut self) -> Result<Bytes, Response> {
+                    let mut bytes = vec![];
+
+                    while let Some(chunk) = self.chunk().await? {
+                        bytes.extend_from_slice(&chunk);
+                    }
+
+                    Ok(Bytes::from(bytes))
+                }
+
+                /// Read the rest of the body as text, decoded with the charset
+                /// of its byte order mark or of the `Content-Type` header
+                /// (`UTF-8` by default, `ISO-8859-1` and `UTF-16` are supported too)
+                pub async fn text(&mut self) -> Result<String, Response> {
+                    let bytes = self.bytes().await?;
+                    let charset = self.content_type.as_deref().and_then(|ct| {
+                        ct.split(';').skip(1).find_map(|param| {
+                            let (key, value) = param.split_once('=')?;
+                            let charset = key.trim().eq_ignore_ascii_case("charset");
+                            charset.then(|| value.trim().trim_matches('"'))
+                        })
+                    });
+
+                    decode_text(&bytes, charset)
+                }
+
+                /// Read the rest of the body as JSON, deserialized as `T`
+                pub async fn json<T: DeserializeOwned>(&mut self) -> Result<T, Response> {
+                    let bytes = self.bytes().await?;
+                    let mut deserializer = serde_json::Deserializer::from_slice(&bytes);
+                    let value = serde_path_to_error::deserialize(&mut deserializer)
+                        .map_err(|e| invalid_input("JSON body", e))?;
+
+                    deserializer.end().map_err(|e| {
+                        json_response!(400, {"message": format!("Invalid JSON body: {e}")})
+                    })?;
+
+                    Ok(value)
+                }
+
+                /// Read the rest of the body as an URL-encoded form, deserialized as `T`
+                pub async fn form<T: DeserializeOwned>(&mut self) -> Result<T, Response> {
+                    let bytes = self.bytes().await?;
+                    let deserializer = serde_html_form::Deserializer::from_bytes(&bytes);
+
+                    serde_path_to_error::deserialize(deserializer).map_err(|e| invalid_input("form body", e))
+                }
+
+                /// Check that the `Content-Type` of the body (if any) is the `expected` one
+                fn expect_type(&self, expected: &str, matches: impl Fn(&str) -> bool) -> Result<(), Response> {
+                    let Some(content_type) = &self.content_type else {
+                        return Ok(());
+                    };
+                    let mime_type = content_type.split(';').next().unwrap_or_default().trim();
+
+                    if matches(&mime_type.to_ascii_lowercase()) {
+                        return Ok(());
+                    }
+
+                    Err(json_response!(415, {"message": format!("Expected a {expected} body, got `{mime_type}`")}))
+                }
+            }
+
+            /// `400 Bad Request` response to an input that can't be deserialized,
+            /// reporting the path of the invalid field and the reason
+            fn invalid_input<E: Display>(input: &str, error: serde_path_to_error::Error<E>) -> Response {
+                let field = error.path().to_string();
+                let reason = error.inner().to_string();
+
+                json_response!(400, {
+                    "message": format!("Invalid {input}"),
+                    "errors": [{"field": field, "reason": reason}]
+                })
+            }
+
+            impl Stream for Body {
+                type Item = Result<Bytes, BodyError>;
+
+                fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+                    loop {
+                        let Some(incoming) = self.incoming.as_mut() else {
+                            return Poll::Ready(None);
+                        };
+
+                        let frame = match Pin::new(incoming).poll_frame(cx) {
+                            Poll::Pending => return Poll::Pending,
+                            Poll::Ready(Some(Ok(frame))) => frame,
+                            Poll::Ready(Some(Err(e))) => {
+                                self.incoming = None;
+                                return Poll::Ready(Some(Err(BodyError::Read(e))));
+                            }
+                            Poll::Ready(None) => {
+                                self.incoming = None;
+                                return Poll::Ready(None);
+                            }
+                        };
+
+                        // Trailers are skipped
+                        let Ok(data) = frame.into_data() else {
+                            continue;
+                        };
+
+                        self.read += data.len() as u64;
+
+                        if let Some(max) = self.max_size.filter(|max| self.read > *max) {
+                            self.incoming = None;
+                            return Poll::Ready(Some(Err(BodyError::TooLarge(max))));
+                        }
+
+                        return Poll::Ready(Some(Ok(data)));
+                    }
+                }
+            }
+
+            /// Decode the text `bytes` encoded with the `charset` (if any),
+            /// unless they start with a byte order mark
+            fn decode_text(bytes: &[u8], charset: Option<&str>) -> Result<String, Response> {
+                let (charset, bytes) = match bytes {
+                    [0xEF, 0xBB, 0xBF, rest @ ..] => ("utf-8", rest),
+                    [0xFF, 0xFE, rest @ ..] => ("utf-16le", rest),
+                    [0xFE, 0xFF, rest @ ..] => ("utf-16be", rest),
+                    _ => (charset.unwrap_or("utf-8"), bytes),
+                };
+                let invalid = || json_response!(400, {"message": format!("Request body is not valid {charset}")});
+
+                match charset.to_ascii_lowercase().as_str() {
+                    "utf-8" | "utf8" | "us-ascii" | "ascii" => {
+                        String::from_utf8(bytes.to_vec()).map_err(|_| invalid())
+                    }
+                    "iso-8859-1" | "latin1" | "latin-1" => Ok(bytes.iter().map(|b| *b as char).collect()),
+                    charset @ ("utf-16" | "utf-16le" | "utf-16be") => {
+                        if bytes.len() % 2 != 0 {
+                            return Err(invalid());
+                        }
+
+                        let units = bytes
+                            .chunks_exact(2)
+                            .map(|c| match charset {
+                                "utf-16le" => u16::from_le_bytes([c[0], c[1]]),
+                                _ => u16::from_be_bytes([c[0], c[1]]),
+                            })
+                            .collect::<Vec<_>>();
+
+                        String::from_utf16(&units).map_err(|_| invalid())
+                    }
+                    _ => Err(json_response!(415, {"message": format!("Unsupported charset `{charset}`")})),
+                }
+            }
+
+            /// An invalid header name or value
+            #[derive(Debug)]
+            pub struct InvalidHeader(String);
+
+            impl Display for InvalidHeader {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    write!(f, "Invalid header `{}`", self.0)
+                }
+            }
+
+            impl std::error::Error for InvalidHeader {}
+
+            impl From<InvalidHeader> for Response {
+                fn from(error: InvalidHeader) -> Self {
+                    json_response!(500, {"message": error.to_string()})
+                }
+            }
+
+            /// An error raised by the NextRS runtime, convertible to a `Response`
+            #[derive(Debug)]
+            pub enum NextError {
+                Body(BodyError),
+                InvalidHeader(InvalidHeader),
+
+                /// The request doesn't have a `multipart/form-data` body
+                NotMultipart,
+
+                /// The multipart body is malformed
+                Multipart(multer::Error),
+
+                /// An uploaded file name doesn't name a file (e.g. `..`)
+                InvalidFileName(String),
+                Io(io::Error),
+            }
+
+            impl Display for NextError {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    match self {
+                        NextError::Body(e) => e.fmt(f),
+                        NextError::InvalidHeader(e) => e.fmt(f),
+                        NextError::NotMultipart => write!(f, "Expected a multipart body"),
+                        NextError::Multipart(e) => write!(f, "Invalid multipart body: {e}"),
+                        NextError::InvalidFileName(name) => write!(f, "Invalid file name `{name}`"),
+                        NextError::Io(e) => write!(f, "I/O error: {e}"),
+                    }
+                }
+            }
+
+            impl std::error::Error for NextError {
+                fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                    match self {
+                        NextError::Body(e) => Some(e),
+                        NextError::InvalidHeader(e) => Some(e),
+                        NextError::NotMultipart | NextError::InvalidFileName(_) => None,
+                        NextError::Multipart(e) => Some(e),
+                        NextError::Io(e) => Some(e),
+                    }
+                }
+            }
+
+            impl From<BodyError> for NextError {
+                fn from(error: BodyError) -> Self {
+                    NextError::Body(error)
+                }
+            }
+
+            impl From<InvalidHeader> for NextError {
+                fn from(error: InvalidHeader) -> Self {
+                    NextError::InvalidHeader(error)
+                }
+            }
+
+            impl From<multer::Error> for NextError {
+                fn from(error: multer::Error) -> Self {
+                    // Errors reading the underlying `Body` keep their own status
+                    if let multer::Error::StreamReadFailed(e) = error {
+                        return match e.downcast::<BodyError>() {
+                            Ok(e) => NextError::Body(*e),
+                            Err(e) => NextError::Multipart(multer::Error::StreamReadFailed(e)),
+                        };
+                    }
+
+                    NextError::Multipart(error)
+                }
+            }
+
+            impl From<io::Error> for NextError {
+                fn from(error: io::Error) -> Self {
+                    NextError::Io(error)
+                }
+            }
+
+            impl From<NextError> for Response {
+                fn from(error: NextError) -> Self {
+                    match error {
+                        NextError::Body(e) => e.into(),
+                        NextError::InvalidHeader(e) => e.into(),
+                        NextError::NotMultipart | NextError::Multipart(_) | NextError::InvalidFileName(_) => {
+                            json_response!(400, {"message": error.to_string()})
+                        }
+                        // The details of server-side failures aren't disclosed
+                        NextError::Io(_) => json_response!(500, {"message": "Internal server error"}),
+                    }
+                }
+            }
+
+            /// The headers of a request or of a response: a case-insensitive
+            /// map of names to (possibly multiple) values, backed by hyper's `HeaderMap`
+            #[derive(Debug, Clone, Default, PartialEq)]
+            pub struct Headers(HeaderMap);
+
+            impl Headers {
+                pub fn new() -> Self {
+                    Self::default()
+                }
+
+                /// Create the headers from static `(name, value)` pairs,
+                /// panicking if any of them is invalid
+                pub fn from_static(pairs: &[(&'static str, &'static str)]) -> Self {
+                    let mut headers = Self::new();
+
+                    for (name, value) in pairs {
+                        headers
+                            .0
+                            .append(HeaderName::from_static(name), HeaderValue::from_static(value));
+                    }
+
+                    headers
+                }
+
+                /// Get the first value of the header `name`, if it is valid text
+                pub fn get(&self, name: &str) -> Option<&str> {
+                    self.0.get(name).and_then(|v| v.to_str().ok())
+                }
+
+                /// Get all the values of the header `name` which are valid text
+                pub fn get_all(&self, name: &str) -> Vec<&str> {
+                    self.0
+                        .get_all(name)
+                        .iter()
+                        .filter_map(|v| v.to_str().ok())
+                        .collect()
+                }
+
+                pub fn contains(&self, name: &str) -> bool {
+                    self.0.contains_key(name)
+                }
+
+                /// Set the header `name` to `value`, replacing its previous values
+                pub fn insert(&mut self, name: &str, value: &str) -> Result<(), InvalidHeader> {
+                    let (name, value) = Self::parse(name, value)?;
+                    self.0.insert(name, value);
+                    Ok(())
+                }
+
+                /// Add a `value` to the header `name`, keeping its previous values
+                pub fn append(&mut self, name: &str, value: &str) -> Result<(), InvalidHeader> {
+                    let (name, value) = Self::parse(name, value)?;
+                    self.0.append(name, value);
+                    Ok(())
+                }
+
+                /// Remove all the values of the header `name`, returning whether it was set
+                pub fn remove(&mut self, name: &str) -> bool {
+                    self.0.remove(name).is_some()
+                }
+
+                /// Iterate over the `(name, value)` pairs whose value is valid text
+                /// (the other ones are only accessible through `as_map`)
+                pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+                    self.0
+                        .iter()
+                        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+                }
+
+                pub fn len(&self) -> usize {
+                    self.0.len()
+                }
+
+                pub fn is_empty(&self) -> bool {
+                    self.0.is_empty()
+                }
+
+                pub fn as_map(&self) -> &HeaderMap {
+                    &self.0
+                }
+
+                pub fn as_map_mut(&mut self) -> &mut HeaderMap {
+                    &mut self.0
+                }
+
+                pub fn content_type(&self) -> Option<&str> {
+                    self.get("content-type")
+                }
+
+                pub fn content_length(&self) -> Option<u64> {
+                    self.get("content-length")?.trim().parse().ok()
+                }
+
+                pub fn authorization(&self) -> Option<&str> {
+                    self.get("authorization")
+                }
+
+                /// Get the token of a `Bearer` authorization
+                pub fn bearer_token(&self) -> Option<&str> {
+                    let (scheme, token) = self.authorization()?.split_once(' ')?;
+                    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+                }
+
+                pub fn accept(&self) -> Option<&str> {
+                    self.get("accept")
+                }
+
+                pub fn host(&self) -> Option<&str> {
+                    self.get("host")
+                }
+
+                pub fn user_agent(&self) -> Option<&str> {
+                    self.get("user-agent")
+                }
+
+                /// Get the value of the cookie `name`
+                pub fn cookie(&self, name: &str) -> Option<&str> {
+                    self.get_all("cookie")
+                        .into_iter()
+                        .flat_map(|cookies| cookies.split(';'))
+                        .find_map(|cookie| {
+                            let (key, value) = cookie.split_once('=')?;
+                            (key.trim() == name).then(|| value.trim())
+                        })
+                }
+
+                fn parse(name: &str, value: &str) -> Result<(HeaderName, HeaderValue), InvalidHeader> {
+                    let invalid = || InvalidHeader(name.to_string());
+
+                    Ok((
+                        HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?,
+                        HeaderValue::from_str(value).map_err(|_| invalid())?,
+                    ))
+                }
+            }
+
+            impl From<HeaderMap> for Headers {
+                fn from(map: HeaderMap) -> Self {
+                    Headers(map)
+                }
+            }
+
+            impl From<Headers> for HeaderMap {
+                fn from(headers: Headers) -> Self {
+                    headers.0
+                }
+            }
+
+            /// The query parameters of a request, in order of appearance.
+            ///
+            /// Keys and values are percent-decoded (`+` is decoded as a space),
+            /// keys without `=` have an empty value and repeated keys keep
+            /// every value.
+            #[derive(Debug, Clone, Default, PartialEq, Eq)]
+            pub struct QueryParams(Vec<(String, String)>);
+
+            impl QueryParams {
+                /// Parse a query string (without the leading `?`)
+                pub fn parse(query: &str) -> Self {
+                    QueryParams(form_urlencoded::parse(query.as_bytes()).into_owned().collect())
+                }
+
+                /// Get the first value of a parameter
+                pub fn get(&self, key: &str) -> Option<&str> {
+                    self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+                }
+
+                /// Get all the values of a parameter, e.g. `["1", "2"]` for `?id=1&id=2`
+                pub fn get_all(&self, key: &str) -> Vec<&str> {
+                    self.0.iter().filter(|(k, _)| k == key).map(|(_, v)| v.as_str()).collect()
+                }
+
+                pub fn contains(&self, key: &str) -> bool {
+                    self.0.iter().any(|(k, _)| k == key)
+                }
+
+                /// Iterate over the `(key, value)` pairs
+                pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+                    self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+                }
+
+                pub fn len(&self) -> usize {
+                    self.0.len()
+                }
+
+                pub fn is_empty(&self) -> bool {
+                    self.0.is_empty()
+                }
+
+                /// Group the values by key
+                pub fn to_multimap(&self) -> HashMap<String, Vec<String>> {
+                    let mut map = HashMap::<String, Vec<String>>::new();
+
+                    for (key, value) in &self.0 {
+                        map.entry(key.clone()).or_default().push(value.clone());
+                    }
+
+                    map
+                }
+            }
+
+            impl IntoIterator for QueryParams {
+                type Item = (String, String);
+                type IntoIter = std::vec::IntoIter<(String, String)>;
+
+                fn into_iter(self) -> Self::IntoIter {
+                    self.0.into_iter()
+                }
+            }
+
+            /// An HTTP Request
+            #[derive(Debug)]
+            pub struct Request<'a> {
+                pub method: ReqMethod,
+                pub path: String,
+                pub body: Body,
+                pub multipart_body: Option<Multipart<'a>>,
+                pub headers: Headers,
+
+                /// If the path is matched against a dynamic route, the
+                /// values of the dynamic fields are stored in this property.
+                ///
+                /// For example:
+                /// If an API handler is created in `src/routes/api/books/__id.rs`
+                /// all requests made to `/api/books/<id>` will be passed to this handler.
+                /// The requests passed to this handler will have their `dyn_fields`
+                /// property set to `Some({"id": DynField::Segment("<id>")})`.
+                ///
+                /// Catch-all segments store the rest of the path as a list:
+                /// a handler in `src/routes/docs/___path.rs` receives the requests
+                /// made to `/docs/a/b` with `dyn_fields` set to
+                /// `Some({"path": DynField::CatchAll(["a", "b"])})`.
+                /// Optional catch-all segments (`src/routes/docs/____path.rs`)
+                /// also match `/docs`, with an empty list.
+                pub dyn_fields: Option<HashMap<String, DynField>>,
+            }
+
+            impl<'a> Request<'_> {
+                /// Read the body as JSON, deserialized as `T`.
+                ///
+                /// Fails with `415 Unsupported Media Type` if the request has
+                /// another `Content-Type`, and with `400 Bad Request` (listing the
+                /// invalid field and the reason) if the body can't be deserialized.
+                ///
+                /// ```rust
+                /// #[derive(Deserialize)]
+                /// pub struct NewBook {
+                ///     title: String,
+                ///     year: u16,
+                /// }
+                ///
+                /// pub async fn post<'a>(mut req: Request<'a>) -> Result<Response, Response> {
+                ///     let book: NewBook = req.json().await?;
+                ///     ...
+                /// }
+                /// ```
+                pub async fn json<T: DeserializeOwned>(&mut self) -> Result<T, Response> {
+                    self.body.expect_type("JSON", |t| t == "application/json" || t.ends_with("+json"))?;
+                    self.body.json().await
+                }
+
+                /// Read the body as an `application/x-www-form-urlencoded` form,
+                /// deserialized as `T` (failing like `json`)
+                pub async fn form<T: DeserializeOwned>(&mut self) -> Result<T, Response> {
+                    self.body.expect_type("form", |t| t == "application/x-www-form-urlencoded")?;
+                    self.body.form().await
+                }
+
+                /// Deserialize the query string as `T` (repeated keys can be
+                /// deserialized as a `Vec`), failing with `400 Bad Request`
+                /// (listing the invalid field and the reason)
+                pub fn query<T: DeserializeOwned>(&self) -> Result<T, Response> {
+                    let deserializer = serde_html_form::Deserializer::from_bytes(self.query_string().as_bytes());
+
+                    serde_path_to_error::deserialize(deserializer).map_err(|e| invalid_input("query string", e))
+                }
+
+                /// The request path, without the query string
+                pub fn path_only(&self) -> &str {
+                    self.path.split_once('?').map_or(self.path.as_str(), |(path, _)| path)
+                }
+
+                /// The raw query string (without the leading `?`), empty if missing
+                pub fn query_string(&self) -> &str {
+                    self.path.split_once('?').map_or("", |(_, query)| query)
+                }
+
+                /// Get the request 'query parameters'.
+                ///
+                /// ```rust
+                /// // GET /api/books?tag=rust&tag=web&q=hello+world
+                /// let params = req.query_params();
+                /// params.get("q"); // Some("hello world")
+                /// params.get_all("tag"); // ["rust", "web"]
+                /// ```
+                pub fn query_params(&self) -> QueryParams {
+                    QueryParams::parse(self.query_string())
+                }
+
+                /// Allow only the specified methods on the handler, returning
+                /// `405 Method not allowed` if any other method is attempted.
+                ///
+                /// Route modules can export per-method handlers (`get`, `post`, ...)
+                /// instead, in which case the methods are checked automatically.
+                ///
+                /// Example:
+                /// ```rust
+                /// pub async fn handler(req: Request) -> Result<Response, Response> {
+                ///     req.allow_methods(vec![ReqMethod::Get])?;
+                ///
+                ///     Ok(Response::from_string(
+                ///         200,
+                ///         None,
+                ///         Some(&format!("Hi from {}", req.path)),
+                ///     ))
+                /// }
+                /// ```
+                pub fn allow_methods(&self, methods: Vec<ReqMethod>) -> Result<(), Response> {
+                    if methods.contains(&self.method) {
+                        return Ok(());
+                    }
+
+                    if self.method == ReqMethod::Options {
+                        return Err(options_response(&methods));
+                    }
+
+                    Err(method_not_allowed(&methods))
+                }
+
+                /// Processes the multipart body of the request,
+                /// uploading the files to the specified `dest`ination.
+                /// The resulting Map contains the fields values and the
+                /// path of the uploaded files, named after the last component
+                /// of their client-supplied name.
+                ///
+                /// Fails with `NextError::NotMultipart` if the request doesn't have
+                /// a multipart body, with `NextError::InvalidFileName` if a file name
+                /// doesn't name a file (e.g. `..`), and with `NextError::Io` if `dest`
+                /// isn't a directory or a file can't be written.
+                pub async fn process_upload<P>(self, dest: P) -> Result<HashMap<String, String>, NextError>
+                where
+                    P: AsRef<Path>,
+                {
+                    let Some(mut multipart_body) = self.multipart_body else {
+                        return Err(NextError::NotMultipart);
+                    };
+
+                    if !tokio::fs::metadata(dest.as_ref()).await?.is_dir() {
+                        return Err(io::Error::other(format!(
+                            "Upload destination `{}` is not a directory",
+                            dest.as_ref().display()
+                        ))
+                        .into());
+                    }
+
+                    let mut fields = HashMap::<String, String>::new();
+
+                    while let Some(mut field) = multipart_body.next_field().await? {
+                        let Some(name) = field.name().map(|n| n.to_string()) else {
+                            continue;
+                        };
+
+                        let value = if let Some(fname) = field.file_name() {
+                            // Only the last component of the client-supplied name is
+                            // kept, so that the file can't be written outside `dest`
+                            let Some(file_name) = Path::new(fname).file_name() else {
+                                return Err(NextError::InvalidFileName(fname.to_string()));
+                            };
+                            let path = dest.as_ref().join(file_name);
+                            let mut file = tokio::fs::File::create(&path).await?;
+
+                            while let Some(chunk) = field.chunk().await? {
+                                file.write_all(&chunk).await?;
+                            }
+                            file.flush().await?;
+
+                            path.to_string_lossy().into_owned()
+                        } else {
+                            field.text().await?
+                        };
+
+                        fields.insert(name, value);
+                    }
+
+                    Ok(fields)
+                }
+            }
+
+            /// The body of a `Response`
+            #[derive(Default)]
+            pub enum ResponseBody {
+                #[default]
+                Empty,
+                Full(Vec<u8>),
+
+                /// A body sent to the client chunk by chunk
+                Stream(Pin<Box<dyn Stream<Item = Result<Bytes, io::Error>> + Send>>),
+            }
+
+            impl std::fmt::Debug for ResponseBody {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    match self {
+                        ResponseBody::Empty => write!(f, "Empty"),
+                        ResponseBody::Full(bytes) => f.debug_tuple("Full").field(bytes).finish(),
+                        ResponseBody::Stream(_) => write!(f, "Stream(..)"),
+                    }
+                }
+            }
+
+            impl From<Vec<u8>> for ResponseBody {
+                fn from(bytes: Vec<u8>) -> Self {
+                    ResponseBody::Full(bytes)
+                }
+            }
+
+            impl From<Option<Vec<u8>>> for ResponseBody {
+                fn from(bytes: Option<Vec<u8>>) -> Self {
+                    bytes.map_or(ResponseBody::Empty, ResponseBody::Full)
+                }
+            }
+
+            impl ResponseBody {
+                /// Get the bytes of a non-streamed body
+                pub fn as_bytes(&self) -> Option<&[u8]> {
+                    match self {
+                        ResponseBody::Full(bytes) => Some(bytes),
+                        _ => None,
+                    }
+                }
+
+                fn into_http(self) -> HttpBody {
+                    match self {
+                        ResponseBody::Empty => full_body(Bytes::new()),
+                        ResponseBody::Full(bytes) => full_body(Bytes::from(bytes)),
+                        ResponseBody::Stream(stream) => {
+                            StreamBody::new(stream.map(|chunk| chunk.map(Frame::data))).boxed_unsync()
+                        }
+                    }
+                }
+            }
+
+            /// An event sent by a `Response::sse` stream
+            #[derive(Debug, Clone, Default)]
+            pub struct SseEvent {
+                id: Option<String>,
+                event: Option<String>,
+                data: String,
+                retry: Option<Duration>,
+            }
+
+            impl SseEvent {
+                /// Create an unnamed event carrying the `data`
+                pub fn new(data: impl Display) -> Self {
+                    SseEvent {
+                        data: data.to_string(),
+                        ..Default::default()
+                    }
+                }
+
+                /// Create an unnamed event carrying the `value` serialized as JSON
+                pub fn json(value: &Value) -> Self {
+                    Self::new(value)
+                }
+
+                /// Set the `id` of the event, sent back by the client
+                /// in the `Last-Event-ID` header when reconnecting
+                pub fn id(mut self, id: impl Display) -> Self {
+                    self.id = Some(id.to_string());
+                    self
+                }
+
+                /// Set the `event` name, dispatched to the matching listeners of the client
+                pub fn event(mut self, event: impl Display) -> Self {
+                    self.event = Some(event.to_string());
+                    self
+                }
+
+                /// Set the time the client waits before reconnecting
+                pub fn retry(mut self, retry: Duration) -> Self {
+                    self.retry = Some(retry);
+                    self
+                }
+
+                fn to_bytes(&self) -> Bytes {
+                    let mut event = String::new();
+
+                    if let Some(name) = &self.event {
+                        event.push_str(&format!("event: {}\n", name.replace(['\r', '\n'], "")));
+                    }
+
+                    if let Some(id) = &self.id {
+                        event.push_str(&format!("id: {}\n", id.replace(['\r', '\n'], "")));
+                    }
+
+                    if let Some(retry) = self.retry {
+                        event.push_str(&format!("retry: {}\n", retry.as_millis()));
+                    }
+
+                    // Every line gets a `data:` field, empty ones included,
+                    // so that an event with empty data is still dispatched
+                    for line in self.data.split('\n') {
+                        event.push_str(&format!("data: {}\n", line.strip_suffix('\r').unwrap_or(line)));
+                    }
+
+                    event.push('\n');
+                    Bytes::from(event)
+                }
+            }
+
+            /// An HTTP Response
+            #[derive(Debug)]
+            pub struct Response {
+                pub code: u16,
+                pub headers: Headers,
+                pub body: ResponseBody,
+            }
+
+            impl Response {
+                /// Create a `Response` with the given `code`, `headers` and `body`
+                pub fn from_string(code: u16, headers: Option<Headers>, body: Option<&str>) -> Self {
+                    Response {
+                        code,
+                        headers: headers.unwrap_or_default(),
+                        body: body.map(|b| b.as_bytes().to_vec()).into(),
+                    }
+                }
+
+                /// Create a `Response` with the given `code` and `headers`,
+                /// streaming the chunks of the `body`
+                pub fn stream<S>(code: u16, headers: Option<Headers>, body: S) -> Self
+                where
+                    S: Stream<Item = Bytes> + Send + 'static,
+                {
+                    Response {
+                        code,
+                        headers: headers.unwrap_or_default(),
+                        body: ResponseBody::Stream(Box::pin(body.map(Ok))),
+                    }
+                }
+
+                /// Create a `Response` with the given `code` and `headers`,
+                /// streaming the content of the `reader`
+                pub fn reader<R>(code: u16, headers: Option<Headers>, reader: R) -> Self
+                where
+                    R: AsyncRead + Send + 'static,
+                {
+                    let chunks = stream::unfold(Some(Box::pin(reader)), |reader| async move {
+                        let mut reader = reader?;
+                        let mut buffer = vec![0; 8192];
+
+                        match reader.read(&mut buffer).await {
+                            Ok(0) => None,
+                            Ok(read) => {
+                                buffer.truncate(read);
+                                Some((Ok(Bytes::from(buffer)), Some(reader)))
+                            }
+                            Err(e) => Some((Err(e), None)),
+                        }
+                    });
+
+                    Response {
+                        code,
+                        headers: headers.unwrap_or_default(),
+                        body: ResponseBody::Stream(Box::pin(chunks)),
+                    }
+                }
+
+                /// Create a `Response` with the given `code` and `headers`,
+                /// streaming the content of the file at `path`
+                /// (`404 Not found` if it doesn't exist)
+                pub async fn file<P>(code: u16, headers: Option<Headers>, path: P) -> Result<Self, Response>
+                where
+                    P: AsRef<Path>,
+                {
+                    let file = tokio::fs::File::open(path).await.map_err(|e| match e.kind() {
+                        io::ErrorKind::NotFound => json_response!(404, {"message": "Not found"}),
+                        _ => json_response!(500, {"message": format!("Cannot read the file: {e}")}),
+                    })?;
+                    let length = file.metadata().await.map(|m| m.len()).ok();
+
+                    let mut headers = headers.unwrap_or_default();
+                    if let Some(length) = length {
+                        headers.as_map_mut().insert(CONTENT_LENGTH, HeaderValue::from(length));
+                    }
+
+                    Ok(Self::reader(code, Some(headers), file))
+                }
+
+                /// Start building a response with the status `code`
+                pub fn builder(code: u16) -> ResponseBuilder {
+                    ResponseBuilder {
+                        code,
+                        headers: Headers::new(),
+                        invalid: None,
+                    }
+                }
+
+                /// Start building a `200 OK` response
+                pub fn ok() -> ResponseBuilder {
+                    Self::builder(200)
+                }
+
+                /// Start building a `201 Created` response
+                pub fn created() -> ResponseBuilder {
+                    Self::builder(201)
+                }
+
+                /// Start building a `202 Accepted` response
+                pub fn accepted() -> ResponseBuilder {
+                    Self::builder(202)
+                }
+
+                /// Start building a `400 Bad Request` response
+                pub fn bad_request() -> ResponseBuilder {
+                    Self::builder(400)
+                }
+
+                /// Start building a `401 Unauthorized` response
+                pub fn unauthorized() -> ResponseBuilder {
+                    Self::builder(401)
+                }
+
+                /// Start building a `403 Forbidden` response
+                pub fn forbidden() -> ResponseBuilder {
+                    Self::builder(403)
+                }
+
+                /// Start building a `404 Not Found` response
+                pub fn not_found() -> ResponseBuilder {
+                    Self::builder(404)
+                }
+
+                /// Start building a `409 Conflict` response
+                pub fn conflict() -> ResponseBuilder {
+                    Self::builder(409)
+                }
+
+                /// Start building a `500 Internal Server Error` response
+                pub fn internal_error() -> ResponseBuilder {
+                    Self::builder(500)
+                }
+
+                /// A `204 No Content` response
+                pub fn no_content() -> Self {
+                    Self::builder(204).empty()
+                }
+
+                /// A `302 Found` response, redirecting to the `location`
+                pub fn redirect(location: &str) -> Self {
+                    Self::builder(302).header("location", location).empty()
+                }
+
+                /// A `308 Permanent Redirect` response, redirecting to the `location`
+                pub fn redirect_permanent(location: &str) -> Self {
+                    Self::builder(308).header("location", location).empty()
+                }
+
+                /// Create a `200 OK` Server-Sent Events response, streaming the
+                /// `events` and, if `keep_alive` is set, a comment after each
+                /// `keep_alive` interval without events (so that proxies don't
+                /// close the idle connection)
+                pub fn sse<S>(events: S, keep_alive: Option<Duration>) -> Self
+                where
+                    S: Stream<Item = SseEvent> + Send + 'static,
+                {
+                    let keep_alive = keep_alive.map(|period| {
+                        let mut timer = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                        timer
+                    });
+
+                    let chunks = stream::unfold((Box::pin(events), keep_alive), |(mut events, mut timer)| async move {
+                        let chunk = match &mut timer {
+                            Some(timer) => tokio::select! {
+                                event = events.next() => {
+                                    timer.reset();
+                                    event?.to_bytes()
+                                }
+                                _ = timer.tick() => Bytes::from_static(b": keep-alive\n\n"),
+                            },
+                            None => events.next().await?.to_bytes(),
+                        };
+
+                        Some((chunk, (events, timer)))
+                    });
+
+                    Self::stream(
+                        200,
+                        Some(Headers::from_static(&[
+                            ("content-type", "text/event-stream"),
+                            ("cache-control", "no-cache"),
+                        ])),
+                        chunks,
+                    )
+                }
+            }
+
+            /// A `Response` being built, see `Response::ok`.
+            ///
+            /// ```rust
+            /// Response::created()
+            ///     .header("Location", &format!("/api/books/{id}"))
+            ///     .json(&book)
+            /// ```
+            ///
+            /// If a header is invalid, the built response is a
+            /// `500 Internal Server Error` reporting it.
+            #[derive(Debug)]
+            pub struct ResponseBuilder {
+                code: u16,
+                headers: Headers,
+                invalid: Option<InvalidHeader>,
+            }
+
+            impl ResponseBuilder {
+                /// Add a `value` to the header `name`
+                pub fn header(mut self, name: &str, value: &str) -> Self {
+                    if let Err(e) = self.headers.append(name, value) {
+                        self.invalid.get_or_insert(e);
+                    }
+
+                    self
+                }
+
+                /// Add the `headers`
+                pub fn headers(mut self, headers: Headers) -> Self {
+                    self.headers.as_map_mut().extend(HeaderMap::from(headers));
+                    self
+                }
+
+                /// Set the `Content-Type` header, replacing the default one of the body
+                pub fn content_type(mut self, content_type: &str) -> Self {
+                    if let Err(e) = self.headers.insert("content-type", content_type) {
+                        self.invalid.get_or_insert(e);
+                    }
+
+                    self
+                }
+
+                /// Finish the response with the `body`
+                pub fn body(self, body: impl Into<ResponseBody>) -> Response {
+                    if let Some(invalid) = self.invalid {
+                        return invalid.into();
+                    }
+
+                    Response {
+                        code: self.code,
+                        headers: self.headers,
+                        body: body.into(),
+                    }
+                }
+
+                /// Finish the response without a body
+                pub fn empty(self) -> Response {
+                    self.body(ResponseBody::Empty)
+                }
+
+                /// Finish the response with a `text/plain` body
+                pub fn text(self, text: impl Into<String>) -> Response {
+                    self.default_type("text/plain; charset=utf-8")
+                        .body(text.into().into_bytes())
+                }
+
+                /// Finish the response with a `text/html` body
+                pub fn html(self, html: impl Into<String>) -> Response {
+                    self.default_type("text/html; charset=utf-8")
+                        .body(html.into().into_bytes())
+                }
+
+                /// Finish the response with an `application/octet-stream` body
+                pub fn bytes(self, bytes: impl Into<Vec<u8>>) -> Response {
+                    self.default_type("application/octet-stream").body(bytes.into())
+                }
+
+                /// Finish the response with the `value` serialized as JSON
+                pub fn json<T: Serialize + ?Sized>(self, value: &T) -> Response {
+                    match serde_json::to_vec(value) {
+                        Ok(bytes) => self.default_type("application/json").body(bytes),
+                        Err(e) => json_response!(500, {"message": format!("Cannot serialize the response: {e}")}),
+                    }
+                }
+
+                /// Finish the response streaming the chunks of the `body`
+                pub fn stream<S>(self, body: S) -> Response
+                where
+                    S: Stream<Item = Bytes> + Send + 'static,
+                {
+                    self.body(ResponseBody::Stream(Box::pin(body.map(Ok))))
+                }
+
+                fn default_type(mut self, content_type: &'static str) -> Self {
+                    if !self.headers.contains("content-type") {
+                        self.headers
+                            .as_map_mut()
+                            .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+                    }
+
+                    self
+                }
+            }
+
+            /// A value serialized as a JSON response
+            pub struct Json<T>(pub T);
+
+            /// Conversion of the values returned by the handlers into a `Response`,
+            /// so that handlers can return `Result<impl IntoResponse, impl IntoResponse>`
+            pub trait IntoResponse {
+                fn into_response(self) -> Response;
+            }
+
+            impl IntoResponse for Response {
+                fn into_response(self) -> Response {
+                    self
+                }
+            }
+
+            impl IntoResponse for ResponseBuilder {
+                fn into_response(self) -> Response {
+                    self.empty()
+                }
+            }
+
+            impl IntoResponse for () {
+                fn into_response(self) -> Response {
+                    Response::ok().empty()
+                }
+            }
+
+            impl IntoResponse for &'static str {
+                fn into_response(self) -> Response {
+                    Response::ok().text(self)
+                }
+            }
+
+            impl IntoResponse for String {
+                fn into_response(self) -> Response {
+                    Response::ok().text(self)
+                }
+            }
+
+            impl IntoResponse for Vec<u8> {
+                fn into_response(self) -> Response {
+                    Response::ok().bytes(self)
+                }
+            }
+
+            impl IntoResponse for Bytes {
+                fn into_response(self) -> Response {
+                    Response::ok().bytes(self)
+                }
+            }
+
+            impl IntoResponse for Value {
+                fn into_response(self) -> Response {
+                    Response::ok().json(&self)
+                }
+            }
+
+            impl<T: Serialize> IntoResponse for Json<T> {
+                fn into_response(self) -> Response {
+                    Response::ok().json(&self.0)
+                }
+            }
+
+            impl IntoResponse for InvalidHeader {
+                fn into_response(self) -> Response {
+                    self.into()
+                }
+            }
+
+            impl IntoResponse for BodyError {
+                fn into_response(self) -> Response {
+                    self.into()
+                }
+            }
+
+            impl IntoResponse for NextError {
+                fn into_response(self) -> Response {
+                    self.into()
+                }
+            }
+
+            /// A response with the status `code` (e.g. `(201, Json(book))`)
+            impl<T: IntoResponse> IntoResponse for (u16, T) {
+                fn into_response(self) -> Response {
+                    let mut response = self.1.into_response();
+                    response.code = self.0;
+                    response
+                }
+            }
+
+            impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+                fn into_response(self) -> Response {
+                    match self {
+                        Ok(response) => response.into_response(),
+                        Err(response) => response.into_response(),
+                    }
+                }
+            }
+
+            impl From<ResponseBuilder> for Response {
+                fn from(builder: ResponseBuilder) -> Self {
+                    builder.into_response()
+                }
+            }
+
+            impl From<&'static str> for Response {
+                fn from(text: &'static str) -> Self {
+                    text.into_response()
+                }
+            }
+
+            impl From<String> for Response {
+                fn from(text: String) -> Self {
+                    text.into_response()
+                }
+            }
+
+            impl From<Vec<u8>> for Response {
+                fn from(bytes: Vec<u8>) -> Self {
+                    bytes.into_response()
+                }
+            }
+
+            impl From<Value> for Response {
+                fn from(value: Value) -> Self {
+                    value.into_response()
+                }
+            }
+
+            /// Convert the `result` of a handler into the result of the handlers chain
+            fn into_result<T, E>(result: Result<T, E>) -> Result<Response, Response>
+            where
+                T: IntoResponse,
+                E: IntoResponse,
+            {
+                result.map(T::into_response).map_err(E::into_response)
+            }
+
+            /// Format the `allowed` methods as the value of an `Allow` header
+            fn allow_header(allowed: &[ReqMethod]) -> String {
+                allowed
+                    .iter()
+                    .map(|m| m.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+
+            /// `405 Method not allowed` response, listing the `allowed` methods
+            fn method_not_allowed(allowed: &[ReqMethod]) -> Response {
+                let mut response = json_response!(405, {"message": "Method not allowed"});
+                set_header(response.headers.as_map_mut(), ALLOW, &allow_header(allowed));
+                response
+            }
+
+            /// `501 Not implemented` response to a non-standard method
+            fn not_implemented() -> Response {
+                json_response!(501, {"message": "Not implemented"})
+            }
+
+            /// Response to an `OPTIONS` request, listing the `allowed` methods
+            fn options_response(allowed: &[ReqMethod]) -> Response {
+                let mut response = Response::from_string(204, None, None);
+                set_header(response.headers.as_map_mut(), ALLOW, &allow_header(allowed));
+                response
+            }
+
+            /// `200 OK` response serving an embedded static file
+            fn static_response(content_type: Option<&'static str>, body: &'static [u8]) -> Response {
+                let mut headers = Headers::new();
+
+                if let Some(content_type) = content_type {
+                    headers
+                        .as_map_mut()
+                        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+                }
+
+                Response {
+                    code: 200,
+                    headers,
+                    body: ResponseBody::Full(body.to_vec()),
+                }
+            }
+
+            /// A failed request, passed to the `render` function of the error
+            /// pages (`_404.rs`, `_500.rs` and `_error.rs` route files)
+            #[derive(Debug)]
+            pub struct ErrorInfo {
+                pub method: ReqMethod,
+                pub path: String,
+
+                /// Headers of the request
+                pub headers: Headers,
+
+                /// The error response replaced by the page
+                pub response: Response,
+            }
+
+            impl ErrorInfo {
+                /// The request path, without the query string
+                pub fn path_only(&self) -> &str {
+                    self.path.split_once('?').map_or(self.path.as_str(), |(path, _)| path)
+                }
+            }
+
+            /// Error `response` with the body of a static error page,
+            /// keeping its status and headers
+            fn static_error_page(error: ErrorInfo, content_type: Option<&'static str>, body: &'static [u8]) -> Response {
+                let mut response = error.response;
+                let headers = response.headers.as_map_mut();
+
+                headers.remove(CONTENT_LENGTH);
+                match content_type {
+                    Some(content_type) => headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type)),
+                    None => headers.remove(CONTENT_TYPE),
+                };
+
+                response.body = ResponseBody::Full(body.to_vec());
+                response
+            }
+
+            type HandlerFuture<'a> = Pin<Box<dyn Future<Output = Result<Response, Response>> + Send + 'a>>;
+
+            /// The rest of the handlers chain, passed to the middlewares
+            pub struct Next<'a> {
+                handler: Box<dyn FnOnce(Request<'a>) -> HandlerFuture<'a> + Send + 'a>,
+            }
+
+            impl<'a> Next<'a> {
+                pub fn new<F, Fut>(handler: F) -> Self
+                where
+                    F: FnOnce(Request<'a>) -> Fut + Send + 'a,
+                    Fut: Future<Output = Result<Response, Response>> + Send + 'a,
+                {
+                    Next {
+                        handler: Box::new(move |req| Box::pin(handler(req))),
+                    }
+                }
+
+                /// Pass the `req` to the next middleware (or to the route handler)
+                pub async fn run(self, req: Request<'a>) -> Result<Response, Response> {
+                    (self.handler)(req).await
+                }
+            }
+
+            /// A node of the routes trie, matching a path segment
+            struct RouteNode {
+                /// Index of the route matching the path ending at this node
+                route: Option<usize>,
+
+                /// Children matching static segments, sorted by segment
+                statics: &'static [(&'static str, RouteNode)],
+
+                /// Children matching the segments accepted by their validator
+                /// (any segment if none), tried in order
+                dynamic: &'static [(Option<fn(&str) -> bool>, RouteNode)],
+
+                /// Index of the route matching any non-empty rest of the path
+                catch_all: Option<usize>,
+            }
+
+            impl RouteNode {
+                /// Find the route matching the path `segments`, storing the
+                /// values of the dynamic and catch-all segments in `values`.
+                ///
+                /// Static segments take precedence over dynamic ones, which take
+                /// precedence over catch-all ones: if a subtree doesn't match the
+                /// rest of the path, the next one is tried.
+                fn find<'p>(&self, segments: &[&'p str], values: &mut Vec<&'p str>) -> Option<usize> {
+                    let Some((segment, rest)) = segments.split_first() else {
+                        return self.route;
+                    };
+
+                    if let Ok(i) = self.statics.binary_search_by(|(s, _)| (*s).cmp(segment)) {
+                        if let Some(route) = self.statics[i].1.find(rest, values) {
+                            return Some(route);
+                        }
+                    }
+
+                    for (validate, dynamic) in self.dynamic {
+                        if validate.is_some_and(|validate| !validate(segment)) {
+                            continue;
+                        }
+
+                        values.push(segment);
+
+                        if let Some(route) = dynamic.find(rest, values) {
+                            return Some(route);
+                        }
+
+                        values.pop();
+                    }
+
+                    if let Some(route) = self.catch_all {
+                        values.extend_from_slice(segments);
+                        return Some(route);
+                    }
+
+                    None
+                }
+            }
+
+            #router
+
+            #error_pages
+
+            /// Check that a typed dynamic `segment` parses as `T`
+            fn parses<T: FromStr>(segment: &str) -> bool {
+                segment.parse::<T>().is_ok()
+            }
+
+            /// Parse the `value` of the route parameter `name`
+            fn parse_param<T: FromStr>(name: &str, value: &str) -> Result<T, Response>
+            where
+                T::Err: Display,
+            {
+                value.parse().map_err(|e: T::Err| {
+                    json_response!(400, {"message": format!("Invalid route parameter `{name}`: {e}")})
+                })
+            }
+
+            /// Parse the `values` of the catch-all route parameter `name`
+            fn parse_params<T: FromStr>(name: &str, values: &[String]) -> Result<Vec<T>, Response>
+            where
+                T::Err: Display,
+            {
+                let mut params = Vec::with_capacity(values.len());
+
+                for value in values {
+                    params.push(parse_param(name, value)?);
+                }
+
+                Ok(params)
+            }
+
+            /// Decode the percent-encoded characters of a path `segment`
+            fn percent_decode(segment: &str) -> String {
+                let bytes = segment.as_bytes();
+                let mut decoded = Vec::with_capacity(bytes.len());
+                let mut i = 0;
+
+                while i < bytes.len() {
+                    let hex = bytes.get(i + 1..i + 3).and_then(|h| {
+                        let hi = (h[0] as char).to_digit(16)?;
+                        let lo = (h[1] as char).to_digit(16)?;
+                        Some((hi * 16 + lo) as u8)
+                    });
+
+                    match (bytes[i], hex) {
+                        (b'%', Some(byte)) => {
+                            decoded.push(byte);
+                            i += 3;
+                        }
+                        (byte, _) => {
+                            decoded.push(byte);
+                            i += 1;
+                        }
+                    }
+                }
+
+                String::from_utf8_lossy(&decoded).into_owned()
+            }
+
+            /// Split a `path` into its percent-decoded segments
+            fn path_segments(path: &str) -> Vec<String> {
+                path.split('/')
+                    .filter(|s| !s.is_empty())
+                    .map(percent_decode)
+                    .collect()
+            }
+
+            /// Check that the path `segments` are in the `scope` of an error
+            /// page (the segments of its directory, `None` matching any value)
+            fn in_scope(segments: &[String], scope: &[Option<&str>]) -> bool {
+                scope.len() <= segments.len()
+                    && scope
+                        .iter()
+                        .zip(segments)
+                        .all(|(scope, segment)| scope.is_none_or(|s| s == segment))
+            }
+
+            /// Quality of the media `ty` (e.g. `text/html`) in an `Accept` header,
+            /// from its most specific matching range
+            fn accept_quality(accept: &str, ty: &str) -> f32 {
+                let (main, _) = ty.split_once('/').unwrap_or((ty, ""));
+                let mut quality = (0, 0.0);
+
+                for range in accept.split(',') {
+                    let mut params = range.split(';').map(str::trim);
+                    let range = params.next().unwrap_or_default().to_ascii_lowercase();
+                    let q = params
+                        .find_map(|p| p.strip_prefix("q="))
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .unwrap_or(1.0);
+
+                    let specificity = if range == ty {
+                        3
+                    } else if range.strip_suffix("/*") == Some(main) {
+                        2
+                    } else if range == "*/*" {
+                        1
+                    } else {
+                        continue;
+                    };
+
+                    if specificity > quality.0 {
+                        quality = (specificity, q);
+                    }
+                }
+
+                quality.1
+            }
+
+            /// Whether a client sending the `accept` header prefers HTML over JSON
+            fn prefers_html(accept: Option<&str>) -> bool {
+                accept.is_some_and(|accept| {
+                    accept_quality(accept, "text/html") > accept_quality(accept, "application/json")
+                })
+            }
+
+            /// Map the dynamic segments `names` of the matched route to their `values`,
+            /// the rest of them being the values of the `catch_all` segment (if any)
+            fn dyn_fields(
+                names: &[&str],
+                catch_all: Option<&str>,
+                values: &[&str],
+            ) -> HashMap<String, DynField> {
+                let mut fields = names
+                    .iter()
+                    .zip(values)
+                    .map(|(name, value)| (name.to_string(), DynField::Segment(value.to_string())))
+                    .collect::<HashMap<_, _>>();
+
+                if let Some(name) = catch_all {
+                    let rest = values[names.len()..].iter().map(|v| v.to_string()).collect();
+                    fields.insert(name.to_string(), DynField::CatchAll(rest));
+                }
+
+                fields
+            }
+
+            async fn handle(mut req: Request<'_>) -> Response {
+                let segments = path_segments(req.path_only());
+                let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+                let mut values: Vec<&str> = vec![];
+
+                let response = match ROUTER.find(&segments, &mut values) {
+                    #(#handlers)*
+                    _ => Err(json_response!(404, {"message": "Not found"})),
+                };
+
+                match response {
+                    Ok(r) => r,
+                    Err(r) => r,
+                }
+            }
+
+            /// Path and query of the request `uri`, which is in absolute
+            /// form for HTTP/2 requests (e.g. `http://host/path?query`)
+            fn request_path(uri: &Uri) -> String {
+                uri.path_and_query().map_or("/", |p| p.as_str()).to_string()
+            }
+
+            /// Map the hyper `req` to a `Request`, failing if its declared
+            /// length exceeds the `max_body_size`
+            fn map_request<'a>(
+                req: HyperRequest<Incoming>,
+                max_body_size: Option<u64>,
+            ) -> Result<Request<'a>, Response> {
+                let content_length = req
+                    .headers()
+                    .get(CONTENT_LENGTH)
+                    .and_then(|l| l.to_str().ok())
+                    .and_then(|l| l.parse::<u64>().ok());
+
+                if let Some(max) = max_body_size.filter(|max| content_length.is_some_and(|l| l > *max)) {
+                    return Err(BodyError::TooLarge(max).into());
+                }
+
+                let (parts, body) = req.into_parts();
+                let mut request = Request {
+                    method: ReqMethod::from(parts.method.as_str()),
+                    path: request_path(&parts.uri),
+                    body: Body::empty(),
+                    headers: Headers::from(parts.headers),
+                    dyn_fields: None,
+                    multipart_body: None,
+                };
+
+                let content_type = request.headers.content_type().map(String::from);
+                let multipart_boundary = content_type
+                    .as_deref()
+                    .and_then(|ct| multer::parse_boundary(ct).ok());
+                let body = Body::new(body, content_type, max_body_size);
+
+                match multipart_boundary {
+                    Some(boundary) => request.multipart_body = Some(Multipart::new(body, boundary)),
+                    None => request.body = body,
+                }
+
+                Ok(request)
+            }
 
-/// HTTP request method
-#[derive(Debug, Clone, PartialEq)]
-pub enum ReqMethod {
-    Get,
-    Patch,
-    Post,
-    Put,
-    Delete,
-    Options,
-    Head,
-    Trace,
-    Connect,
-}
+            /// `500 Internal server error` response to a request whose handler
+            /// panicked, tagged with a request id (in the body and in the
+            /// `X-Request-Id` header) to find the panic in the logs
+            fn panic_response(panic: Box<dyn Any + Send>) -> Response {
+                static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |t| t.as_millis());
+                let request_id = format!("{timestamp:x}-{:x}", NEXT_ID.fetch_add(1, Ordering::Relaxed));
+
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("Box<dyn Any>");
+                eprintln!("Request {request_id} panicked: {message}");
+
+                let mut response = json_response!(500, {
+                    "message": "Internal server error",
+                    "request_id": request_id
+                });
+                set_header(response.headers.as_map_mut(), X_REQUEST_ID, &request_id);
+                response
+            }
 
-impl ReqMethod {
-    pub fn from(string: &str) -> ReqMethod {
-        match string {
-            "GET" => ReqMethod::Get,
-            "PATCH" => ReqMethod::Patch,
-            "POST" => ReqMethod::Post,
-            "PUT" => ReqMethod::Put,
-            "DELETE" => ReqMethod::Delete,
-            "OPTIONS" => ReqMethod::Options,
-            "HEAD" => ReqMethod::Head,
-            "TRACE" => ReqMethod::Trace,
-            "CONNECT" => ReqMethod::Connect,
-            _ => panic!("Invalid method"),
-        }
-    }
-}
+            async fn handle_std_request(
+                req: HyperRequest<Incoming>,
+                config: Arc<ServerConfig>,
+            ) -> Result<HyperResponse<HttpBody>, Infallible> {
+                #[cfg(debug_assertions)]
+                let (method, path) = (req.method().clone(), request_path(req.uri()));
+
+                let error_request = HAS_ERROR_PAGES.then(|| {
+                    (
+                        ReqMethod::from(req.method().as_str()),
+                        request_path(req.uri()),
+                        Headers::from(req.headers().clone()),
+                    )
+                });
 
-impl Display for ReqMethod {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                ReqMethod::Get => "GET",
-                ReqMethod::Patch => "PATCH",
-                ReqMethod::Post => "POST",
-                ReqMethod::Put => "PUT",
-                ReqMethod::Delete => "DELETE",
-                ReqMethod::Options => "OPTIONS",
-                ReqMethod::Head => "HEAD",
-                ReqMethod::Trace => "TRACE",
-                ReqMethod::Connect => "CONNECT",
-            }
-        )
-    }
-}
+                let response = match map_request(req, config.max_body_size) {
+                    Ok(request) => {
+                        let handled = AssertUnwindSafe(handle(request)).catch_unwind();
+                        let handled = match config.request_timeout {
+                            Some(timeout) => tokio::time::timeout(timeout, handled).await.ok(),
+                            None => Some(handled.await),
+                        };
+
+                        match handled {
+                            Some(Ok(response)) => response,
+                            Some(Err(panic)) => panic_response(panic),
+                            None => json_response!(504, {"message": "Request timed out"}),
+                        }
+                    }
+                    Err(response) => response,
+                };
+
+                let response = match error_request {
+                    Some((method, path, headers)) if response.code >= 400 => {
+                        let error = ErrorInfo { method, path, headers, response };
+
+                        AssertUnwindSafe(render_error_page(error))
+                            .catch_unwind()
+                            .await
+                            .unwrap_or_else(panic_response)
+                    }
+                    _ => response,
+                };
 
-/// An HTTP Request
-#[derive(Debug)]
-pub struct Request<'a> {
-    pub method: ReqMethod,
-    pub path: String,
-    pub body: Option<String>,
-    pub multipart_body: Option<Multipart<'a>>,
-    pub headers: Vec<String>,
-
-    /// If the path is matched against a dynamic route, the
-    /// values of the dynamic fields are stored in this property.
-    ///
-    /// For example:
-    /// If an API handler is created in `src/routes/api/books/__id.rs`
-    /// all requests made to `/api/books/<id>` will be passed to this handler.
-    /// The requests passed to this handler will have their `dyn_fields`
-    /// property set to `Some({"id": "<id>"})`.
-    pub dyn_fields: Option<HashMap<String, String>>,
-}
+                #[cfg(debug_assertions)]
+                println!("{} {} → {}", method, path, response.code);
 
-impl<'a> Request<'_> {
-    /// Get the request 'query parameters'
-    pub fn query_params(&self) -> Option<HashMap<String, String>> {
-        if !self.path.contains("?") {
-            return None;
-        }
+                let mut res = HyperResponse::new(response.body.into_http());
+                *res.status_mut() = StatusCode::from_u16(response.code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                *res.headers_mut() = response.headers.into();
+
+                Ok(res)
+            }
 
-        let (_, params_string) = self.path.rsplit_once("?").unwrap();
-        let mut query_params: HashMap<String, String> = HashMap::new();
+            async fn handle_request(
+                req: HyperRequest<Incoming>,
+                config: Arc<ServerConfig>,
+            ) -> Result<HyperResponse<HttpBody>, Infallible> {
+                let header = |name: HeaderName| {
+                    req.headers()
+                        .get(name)
+                        .and_then(|v| v.to_str().ok())
+                        .map(String::from)
+                };
+                let origin = header(ORIGIN);
+
+                if let (Some(cors), Some(origin)) = (&config.cors, &origin) {
+                    let preflight = req.method() == Method::OPTIONS
+                        && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+
+                    if preflight {
+                        let requested_headers = header(ACCESS_CONTROL_REQUEST_HEADERS);
+                        return Ok(cors.preflight(origin, requested_headers.as_deref()));
+                    }
+                }
 
-        for param in params_string.split("&") {
-            if !param.contains("=") {
-                continue;
+                // Other upgrade requests (e.g. WebSocket ones) are passed to the routes
+                let result = if req.uri().path().starts_with(SOCKET_IO_PATH) {
+                    config.socket_io.handle_request(req).await
+                } else {
+                    handle_std_request(req, config.clone()).await
+                };
+
+                let (Some(cors), Some(origin)) = (&config.cors, origin) else {
+                    return result;
+                };
+
+                result.map(|mut res| {
+                    cors.apply(&origin, res.headers_mut());
+                    res
+                })
             }
 
-            let split_param: Vec<_> = param.split("=").collect();
-            query_params.insert(String::from(split_param[0]), String::from(split_param[1]));
-        }
+            /// Set the header `name` to `value`, skipping the invalid values
+            fn set_header(headers: &mut HeaderMap, name: HeaderName, value: &str) {
+                if let Ok(value) = HeaderValue::from_str(value) {
+                    headers.insert(name, value);
+                }
+            }
 
-        Some(query_params)
-    }
+            /// An origin allowed by a `CorsPolicy`
+            #[derive(Clone)]
+            enum CorsOrigin {
+                Any,
+                Exact(String),
 
-    /// Allow only the specified methods on the handler, returning
-    /// `400 Bad request` if any other method is attempted.
-    ///
-    /// Example:
-    /// ```rust
-    /// pub async fn handler(req: Request) -> Result<Response, Response> {
-    ///     req.allow_methods(vec![ReqMethod::Get])?;
-    ///
-    ///     Ok(Response::from_string(
-    ///         200,
-    ///         None,
-    ///         Some(&format!("Hi from {}", req.path)),
-    ///     ))
-    /// }
-    /// ```
-    pub fn allow_methods(&self, methods: Vec<ReqMethod>) -> Result<(), Response> {
-        if methods.contains(&self.method) {
-            return Ok(());
-        }
+                /// Origins starting with the prefix and ending with the suffix
+                /// of a wildcard pattern (e.g. `https://*.example.com`)
+                Wildcard(String, String),
+                Predicate(Arc<dyn Fn(&str) -> bool + Send + Sync>),
+            }
 
-        if self.method == ReqMethod::Options {
-            return Err(json_response!(200, ""));
-        }
+            impl CorsOrigin {
+                fn matches(&self, origin: &str) -> bool {
+                    match self {
+                        CorsOrigin::Any => true,
+                        CorsOrigin::Exact(exact) => exact == origin,
+                        CorsOrigin::Wildcard(prefix, suffix) => {
+                            origin.len() > prefix.len() + suffix.len()
+                                && origin.starts_with(prefix.as_str())
+                                && origin.ends_with(suffix.as_str())
+                                && !origin[prefix.len()..origin.len() - suffix.len()].contains('/')
+                        }
+                        CorsOrigin::Predicate(predicate) => predicate(origin),
+                    }
+                }
+            }
 
-        Err(json_response!(400, {"message": "Method not allowed"}))
-    }
+            /// The CORS policy applied by a `WebServer` to its responses.
+            ///
+            /// Preflight requests (`OPTIONS` requests with the `Origin` and
+            /// `Access-Control-Request-Method` headers) are answered directly,
+            /// without calling the route handlers.
+            ///
+            /// ```rust
+            /// let cors = CorsPolicy::new()
+            ///     .allow_origin("https://app.example.com")
+            ///     .allow_origin("https://*.example.com")
+            ///     .allow_credentials(true)
+            ///     .expose_headers(&["X-Request-Id"])
+            ///     .max_age(Duration::from_secs(3600));
+            ///
+            /// let server = WebServer::new(8080).with_cors(cors);
+            /// ```
+            #[derive(Clone)]
+            pub struct CorsPolicy {
+                origins: Vec<CorsOrigin>,
+                methods: Vec<String>,
+
+                /// Allowed request headers, mirroring the requested ones if `None`
+                headers: Option<Vec<String>>,
+                exposed_headers: Vec<String>,
+                credentials: bool,
+                max_age: Option<Duration>,
+            }
 
-    /// Processes the multipart body of the request,
-    /// uploading the files to the specified `dest`ination.
-    /// The resulting Map contains the fields values and the
-    /// path of the uploaded files.
-    pub async fn process_upload<P>(self, dest: P) -> Result<HashMap<String, String>, Response>
-    where
-        P: AsRef<Path>,
-    {
-        if self.multipart_body.is_none() {
-            return Err(json_response!(400, {"message": "Bad request"}));
-        }
+            impl Default for CorsPolicy {
+                fn default() -> Self {
+                    Self::new()
+                }
+            }
 
-        if !metadata(dest.as_ref()).map_or(false, |m| m.is_dir()) {
-            panic!("Speicified destination is not a directory");
-        }
+            impl CorsPolicy {
+                /// Create a policy allowing no origins, with the default
+                /// methods and mirroring the requested headers
+                pub fn new() -> Self {
+                    CorsPolicy {
+                        origins: vec![],
+                        methods: ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"]
+                            .map(String::from)
+                            .to_vec(),
+                        headers: None,
+                        exposed_headers: vec![],
+                        credentials: false,
+                        max_age: None,
+                    }
+                }
 
-        let mut fields = HashMap::<String, String>::new();
-        let mut multipart_body = self.multipart_body.unwrap();
+                /// Create a policy allowing any origin, without credentials
+                pub fn permissive() -> Self {
+                    Self::new().allow_origin("*")
+                }
 
-        while let Some(mut field) = multipart_body.next_field().await.unwrap() {
-            let name = field.name().map(|n| n.to_string());
-            let file_name = field.file_name();
+                /// Allow an `origin`: `*` allows any origin, a `*` in the host
+                /// allows its subdomains (e.g. `https://*.example.com`).
+                ///
+                /// `WebServer::start` fails if `origin` is `*` and credentials are
+                /// allowed, or if a wildcard pattern has no scheme (e.g. `*.example.com`).
+                pub fn allow_origin(mut self, origin: &str) -> Self {
+                    let origin = match origin.split_once('*') {
+                        _ if origin == "*" => CorsOrigin::Any,
+                        Some((prefix, suffix)) => {
+                            CorsOrigin::Wildcard(prefix.to_string(), suffix.to_string())
+                        }
+                        None => CorsOrigin::Exact(origin.trim_end_matches('/').to_string()),
+                    };
+
+                    self.origins.push(origin);
+                    self
+                }
 
-            let value = if let Some(fname) = file_name {
-                let path = dest.as_ref().join(fname);
-                let mut file = File::create(&path).unwrap();
+                /// Allow the origins accepted by the `predicate`
+                pub fn allow_origin_fn<F>(mut self, predicate: F) -> Self
+                where
+                    F: Fn(&str) -> bool + Send + Sync + 'static,
+                {
+                    self.origins.push(CorsOrigin::Predicate(Arc::new(predicate)));
+                    self
+                }
 
-                while let Some(chunk) = field.chunk().await.unwrap() {
-                    file.write(&chunk).ok();
+                /// Set the methods allowed by the preflight responses
+                pub fn allow_methods(mut self, methods: &[&str]) -> Self {
+                    self.methods = methods.iter().map(|m| m.to_uppercase()).collect();
+                    self
                 }
 
-                path.to_str().unwrap().to_string()
-            } else {
-                field.text().await.unwrap_or("".to_string())
-            };
+                /// Set the request headers allowed by the preflight responses
+                /// (by default the requested ones are allowed)
+                pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+                    self.headers = Some(headers.iter().map(|h| h.to_string()).collect());
+                    self
+                }
 
-            fields.insert(name.unwrap(), value);
-        }
+                /// Set the response headers exposed to the browser scripts
+                pub fn expose_headers(mut self, headers: &[&str]) -> Self {
+                    self.exposed_headers = headers.iter().map(|h| h.to_string()).collect();
+                    self
+                }
 
-        Ok(fields)
-    }
-}
+                /// Allow credentials (cookies, authorization headers).
+                ///
+                /// `WebServer::start` fails if any origin (`*`) is allowed, as any site could
+                /// then read the responses to the requests made with the user credentials.
+                pub fn allow_credentials(mut self, credentials: bool) -> Self {
+                    self.credentials = credentials;
+                    self
+                }
 
-/// An HTTP Response
-#[derive(Debug)]
-pub struct Response {
-    pub code: u16,
-    pub headers: Option<Vec<u8>>,
-    pub body: Option<Vec<u8>>,
-}
+                /// Set how long the preflight responses can be cached
+                pub fn max_age(mut self, max_age: Duration) -> Self {
+                    self.max_age = Some(max_age);
+                    self
+                }
 
-impl Response {
-    /// Create a `Response` with the given `code`, `headers` and `body`
-    pub fn from_string(code: u16, headers: Option<&str>, body: Option<&str>) -> Self {
-        Response {
-            code,
-            headers: headers.map(|h| h.as_bytes().to_vec()),
-            body: body.map(|b| b.as_bytes().to_vec()),
-        }
-    }
-}
+                /// Check that the policy can be applied
+                fn check(&self) -> io::Result<()> {
+                    let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+
+                    for origin in &self.origins {
+                        match origin {
+                            CorsOrigin::Any if self.credentials => {
+                                return invalid(String::from("CORS credentials can't be allowed for any origin"));
+                            }
+                            CorsOrigin::Wildcard(prefix, suffix) if !prefix.contains("://") => {
+                                return invalid(format!(
+                                    "CORS origin pattern `{prefix}*{suffix}` has no scheme (e.g. `https://*.example.com`)"
+                                ));
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    Ok(())
+                }
 
-fn get_dynamic_fields(path: &str, dynamic_route: &str) -> Option<HashMap<String, String>> {
-    let regex_src = DYN_FIELDS_REGEX
-        .replace_all(&("^".to_string() + dynamic_route + "$"), "(?P<$field>[\\w\\-_]+)")
-        .to_string();
+                /// Whether the request `origin` is allowed
+                fn allows(&self, origin: &str) -> bool {
+                    self.origins.iter().any(|o| o.matches(origin))
+                }
 
-    let regex = Regex::new(&regex_src).unwrap();
-    let path_match = regex.captures(path);
+                /// Set the headers allowing the request `origin` (if allowed)
+                fn apply(&self, origin: &str, headers: &mut HeaderMap) {
+                    if !self.allows(origin) {
+                        return;
+                    }
+
+                    if self.origins.iter().any(|o| matches!(o, CorsOrigin::Any)) {
+                        set_header(headers, ACCESS_CONTROL_ALLOW_ORIGIN, "*");
+                    } else {
+                        set_header(headers, ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+                        headers.append(VARY, HeaderValue::from_static("Origin"));
+                    }
+
+                    if self.credentials {
+                        set_header(headers, ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
+                    }
+
+                    if !self.exposed_headers.is_empty() {
+                        set_header(headers, ACCESS_CONTROL_EXPOSE_HEADERS, &self.exposed_headers.join(", "));
+                    }
+                }
 
-    if path_match.is_none() {
-        return None;
-    }
+                /// Answer a preflight request from the `origin`,
+                /// asking for the `requested_headers` (if any)
+                fn preflight(
+                    &self,
+                    origin: &str,
+                    requested_headers: Option<&str>,
+                ) -> HyperResponse<HttpBody> {
+                    let mut response = HyperResponse::new(full_body(Bytes::new()));
+                    *response.status_mut() = StatusCode::NO_CONTENT;
+
+                    if !self.allows(origin) {
+                        return response;
+                    }
+
+                    let headers = response.headers_mut();
+                    self.apply(origin, headers);
+
+                    let allowed_headers = match &self.headers {
+                        Some(allowed) => Some(allowed.join(", ")),
+                        None => {
+                            headers.append(VARY, HeaderValue::from_static("Access-Control-Request-Headers"));
+                            requested_headers.map(String::from)
+                        }
+                    };
+
+                    set_header(headers, ACCESS_CONTROL_ALLOW_METHODS, &self.methods.join(", "));
+
+                    if let Some(allowed_headers) = allowed_headers {
+                        set_header(headers, ACCESS_CONTROL_ALLOW_HEADERS, &allowed_headers);
+                    }
+
+                    if let Some(max_age) = self.max_age {
+                        set_header(headers, ACCESS_CONTROL_MAX_AGE, &max_age.as_secs().to_string());
+                    }
+
+                    response
+                }
+            }
 
-    let path_match = path_match.unwrap();
 
-    Some(
-        regex
-            .capture_names()
-            .skip(1)
-            .map(|cn| {
-                let name = cn.unwrap();
-                (
-                    name.to_string(),
-                    path_match.name(name).unwrap().as_str().to_string(),
-                )
-            })
-            .collect(),
-    )
-}
+            /// An address (or an already bound socket) a `WebServer` listens on
+            #[derive(Debug)]
+            enum Bind {
+                Tcp(SocketAddr),
+                TcpListener(std::net::TcpListener),
+                #[cfg(unix)]
+                Unix(PathBuf),
+                #[cfg(unix)]
+                UnixListener(std::os::unix::net::UnixListener),
+            }
 
-fn matches_dynamic_route(path: &str, dynamic_route: &str, req: &mut Request) -> bool {
-    req.dyn_fields = get_dynamic_fields(path, dynamic_route);
-    req.dyn_fields.is_some()
-}
+            impl Bind {
+                async fn listen(&self) -> io::Result<Listener> {
+                    match self {
+                        Bind::Tcp(address) => Ok(Listener::Tcp(TcpListener::bind(address).await?)),
+                        Bind::TcpListener(listener) => {
+                            let listener = listener.try_clone()?;
+                            listener.set_nonblocking(true)?;
+                            Ok(Listener::Tcp(TcpListener::from_std(listener)?))
+                        }
+                        #[cfg(unix)]
+                        Bind::Unix(path) => {
+                            use std::os::unix::fs::FileTypeExt;
+
+                            // Remove the socket left by a previous run
+                            if metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                                std::fs::remove_file(path)?;
+                            }
+
+                            Ok(Listener::Unix(tokio::net::UnixListener::bind(path)?))
+                        }
+                        #[cfg(unix)]
+                        Bind::UnixListener(listener) => {
+                            let listener = listener.try_clone()?;
+                            listener.set_nonblocking(true)?;
+                            Ok(Listener::Unix(tokio::net::UnixListener::from_std(listener)?))
+                        }
+                    }
+                }
+            }
 
-fn get_sio_service() -> SocketIOService {
-    SOCKET_SERVICE.0.lock().unwrap().clone()
-}
+            /// A bound socket accepting the connections of a `WebServer`
+            enum Listener {
+                Tcp(TcpListener),
+                #[cfg(unix)]
+                Unix(tokio::net::UnixListener),
+            }
 
-async fn handle_sio_request(
-    req: HyperRequest<Incoming>,
-) -> Result<HyperResponse<Full<Bytes>>, Infallible> {
-    get_sio_service().call(req).await.map(|res| {
-        // Response mapping
-        let mut response = HyperResponse::builder()
-            .status(res.status())
-            .version(res.version());
-
-        for header in res.headers() {
-            response = response.header(header.0, header.1)
-        }
+            impl Listener {
+                async fn accept(&self) -> io::Result<Connection> {
+                    let stream = match self {
+                        Listener::Tcp(listener) => Socket::Tcp(listener.accept().await?.0),
+                        #[cfg(unix)]
+                        Listener::Unix(listener) => Socket::Unix(listener.accept().await?.0),
+                    };
 
-        let body = Full::new(executor::block_on(async move {
-            res.collect()
-                .await
-                .ok()
-                .map_or(Bytes::new(), |b| b.to_bytes())
-        }));
+                    Ok(Connection { stream, permit: None })
+                }
 
-        response.body(body).unwrap()
-    })
-}
+                /// URL of the server on the listener (e.g. `http://0.0.0.0:8080`)
+                fn url(&self, scheme: &str) -> String {
+                    match self {
+                        Listener::Tcp(listener) => match listener.local_addr() {
+                            Ok(address) => format!("{scheme}://{address}"),
+                            Err(_) => format!("{scheme}://?"),
+                        },
+                        #[cfg(unix)]
+                        Listener::Unix(listener) => {
+                            let path = listener.local_addr().ok();
+                            let path = path.as_ref().and_then(|a| a.as_pathname());
+                            format!("{scheme}+unix://{}", path.map_or("?".into(), |p| p.display().to_string()))
+                        }
+                    }
+                }
+            }
 
-async fn handle(mut req: Request<'_>) -> Response {
-    let req_path = req.path.clone();
-    let clean_path = req_path.split("?").collect::<Vec<_>>()[0].trim_matches('/');
+            /// An accepted connection
+            struct Connection {
+                stream: Socket,
 
-    let response = match clean_path {
-        $handlers // <=
-        _ => Err(json_response!(404, {"message": "Not found"})),
-    };
+                /// Permit of the `max_connections` limit (if any), released
+                /// once the connection is dropped (even if upgraded)
+                permit: Option<OwnedSemaphorePermit>,
+            }
 
-    match response {
-        Ok(r) => r,
-        Err(r) => r,
-    }
-}
+            /// The socket of a `Connection`, over TCP or over a Unix domain socket
+            enum Socket {
+                Tcp(tokio::net::TcpStream),
+                #[cfg(unix)]
+                Unix(tokio::net::UnixStream),
+            }
 
-async fn map_request<'a>(req: HyperRequest<Incoming>) -> Request<'a> {
-    let mut request = Request {
-        method: ReqMethod::from(&req.method().to_string()),
-        path: req.uri().to_string(),
-        body: None,
-        headers: req
-            .headers()
-            .iter()
-            .map(|h| format!("{}: {}", h.0, h.1.to_str().unwrap_or("")))
-            .collect(),
-        dyn_fields: None,
-        multipart_body: None,
-    };
-
-    let multipart_boundary = req
-        .headers()
-        .get(CONTENT_TYPE)
-        .and_then(|ct| ct.to_str().ok())
-        .and_then(|ct| multer::parse_boundary(ct).ok());
-
-    if let Some(boundary) = multipart_boundary {
-        let body_stream = BodyStream::new(req.into_body()).filter_map(|result| async move {
-            result.map(|frame| frame.into_data().ok()).transpose()
-        });
-
-        request.multipart_body = Some(Multipart::new(body_stream, boundary));
-    } else {
-        request.body = req.collect().await.ok().map_or(None, |b| {
-            String::from_utf8(b.to_bytes().iter().cloned().collect()).ok()
-        });
-    }
+            impl AsyncRead for Connection {
+                fn poll_read(
+                    self: Pin<&mut Self>,
+                    cx: &mut Context<'_>,
+                    buf: &mut ReadBuf<'_>,
+                ) -> Poll<io::Result<()>> {
+                    match &mut self.get_mut().stream {
+                        Socket::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+                        #[cfg(unix)]
+                        Socket::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+                    }
+                }
+            }
 
-    request
-}
+            impl AsyncWrite for Connection {
+                fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+                    match &mut self.get_mut().stream {
+                        Socket::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+                        #[cfg(unix)]
+                        Socket::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+                    }
+                }
+
+                fn poll_write_vectored(
+                    self: Pin<&mut Self>,
+                    cx: &mut Context<'_>,
+                    bufs: &[io::IoSlice<'_>],
+                ) -> Poll<io::Result<usize>> {
+                    match &mut self.get_mut().stream {
+                        Socket::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+                        #[cfg(unix)]
+                        Socket::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+                    }
+                }
+
+                fn is_write_vectored(&self) -> bool {
+                    match &self.stream {
+                        Socket::Tcp(stream) => stream.is_write_vectored(),
+                        #[cfg(unix)]
+                        Socket::Unix(stream) => stream.is_write_vectored(),
+                    }
+                }
+
+                fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                    match &mut self.get_mut().stream {
+                        Socket::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+                        #[cfg(unix)]
+                        Socket::Unix(stream) => Pin::new(stream).poll_flush(cx),
+                    }
+                }
+
+                fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                    match &mut self.get_mut().stream {
+                        Socket::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+                        #[cfg(unix)]
+                        Socket::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+                    }
+                }
+            }
+
+            /// A web server (handling both `HTTP` and `socket.io` requests)
+            pub struct WebServer {
+                binds: Vec<Bind>,
+                config: ServerConfig,
+                #tls_field
+            }
 
-async fn handle_std_request(
-    req: HyperRequest<Incoming>,
-) -> Result<HyperResponse<Full<Bytes>>, Infallible> {
-    let request = map_request(req).await;
+            /// The options and the state of a `WebServer`, shared by its connections
+            #[derive(Clone)]
+            struct ServerConfig {
+                socket_io: SocketIO,
+                cors: Option<CorsPolicy>,
 
-    #[cfg(debug_assertions)]
-    let method = request.method.clone();
-    let path = request.path.clone();
+                /// Maximum size (in bytes) of the request bodies
+                max_body_size: Option<u64>,
 
-    let response = handle(request).await;
+                /// Whether HTTP/1.1 connections are kept alive between requests
+                keep_alive: bool,
 
-    #[cfg(debug_assertions)]
-    println!("{} {} → {}", method, path, response.code);
+                /// Maximum number of concurrent streams of the HTTP/2 connections
+                max_concurrent_streams: Option<u32>,
 
-    let mut res = HyperResponse::builder().status(response.code);
+                /// Interval and timeout of the HTTP/2 keep-alive pings
+                http2_keep_alive: Option<(Duration, Duration)>,
 
-    if let Some(hdrs) = response.headers {
-        if let Some(headers) = String::from_utf8(hdrs).ok() {
-            for header in headers.split("\n") {
-                if let Some((key, value)) = header.split_once("=") {
-                    res = res.header(key, value)
+                /// Time given to the open connections to finish their requests
+                /// when the server shuts down
+                shutdown_timeout: Duration,
+
+                /// Maximum number of open connections, the new ones waiting
+                /// to be accepted until some are closed
+                max_connections: Option<usize>,
+
+                /// Time given to the HTTP/1.1 clients to send the headers of a request,
+                /// to the new connections to send their first request and to the
+                /// TLS clients to complete the handshake
+                header_read_timeout: Duration,
+
+                /// Time after which the connections without requests are closed
+                idle_timeout: Option<Duration>,
+
+                /// Time given to the route handlers to answer a request
+                request_timeout: Option<Duration>,
+            }
+
+            impl ServerConfig {
+                /// Builder of the connections, speaking HTTP/1.1 and HTTP/2
+                /// (with prior knowledge) on the same port
+                fn connection_builder(&self) -> auto::Builder<TokioExecutor> {
+                    let mut builder = auto::Builder::new(TokioExecutor::new());
+                    builder
+                        .http1()
+                        .timer(TokioTimer::new())
+                        .keep_alive(self.keep_alive)
+                        .header_read_timeout(self.header_read_timeout);
+
+                    let mut http2 = builder.http2();
+                    http2.timer(TokioTimer::new()).max_concurrent_streams(self.max_concurrent_streams);
+
+                    if let Some((interval, timeout)) = self.http2_keep_alive {
+                        http2.keep_alive_interval(interval).keep_alive_timeout(timeout);
+                    }
+
+                    builder
                 }
             }
-        }
-    }
 
-    let res_body = if let Some(body) = response.body {
-        Full::new(Bytes::from(body))
-    } else {
-        Full::new(Bytes::default())
-    };
+            impl WebServer {
+                /// Create a new `WebServer` listening on the specified `port`.
+                ///
+                /// Each instance owns its `socket.io` service (see `socket_io`), so
+                /// several servers can run in the same process.
+                pub fn new(port: u16) -> Self {
+                    Self::bind([SocketAddr::from(([0, 0, 0, 0], port))])
+                }
 
-    Ok(res.body(res_body).unwrap())
-}
+                /// Create a new `WebServer` listening on the specified `addresses`.
+                ///
+                /// On dual-stack systems an IPv6 wildcard address (`[::]:port`) also
+                /// accepts the IPv4 connections.
+                ///
+                /// ```rust
+                /// let server = WebServer::bind(["127.0.0.1:8080".parse()?, "[::1]:8080".parse()?]);
+                /// ```
+                pub fn bind(addresses: impl IntoIterator<Item = SocketAddr>) -> Self {
+                    WebServer {
+                        binds: addresses.into_iter().map(Bind::Tcp).collect(),
+                        config: ServerConfig {
+                            socket_io: SocketIO::new(),
+                            cors: CORS_ENABLED.then(CorsPolicy::permissive),
+                            max_body_size: None,
+                            keep_alive: true,
+                            max_concurrent_streams: None,
+                            http2_keep_alive: None,
+                            shutdown_timeout: Duration::from_secs(30),
+                            max_connections: None,
+                            header_read_timeout: Duration::from_secs(30),
+                            idle_timeout: None,
+                            request_timeout: None,
+                        },
+                        #tls_init
+                    }
+                }
 
-async fn handle_request(
-    req: HyperRequest<Incoming>,
-) -> Result<HyperResponse<Full<Bytes>>, Infallible> {
-    let result = match (req.uri().path(), req.headers().contains_key("Upgrade")) {
-        (path, header) if header || ["/socket.io", "/ws"].iter().any(|p| path.starts_with(p)) => {
-            handle_sio_request(req).await
-        }
-        _ => handle_std_request(req).await,
-    };
-
-    // TODO: let set custom headers
-    result.map(|mut res| {
-        let headers = res.headers_mut();
-        headers.insert("Access-Control-Allow-Origin", HeaderValue::from_static("*"));
-        headers.insert(
-            "Access-Control-Allow-Methods",
-            HeaderValue::from_static("GET, POST, PATCH, PUT, DELETE, OPTIONS"),
-        );
-        headers.insert(
-            "Access-Control-Allow-Headers",
-            HeaderValue::from_static(
-                "Origin, X-Requested-With, Content-Type, Accept, authorization",
-            ),
-        );
-
-        res
-    })
-}
+                /// The `socket.io` service of the server, handling its namespaces
+                /// and clients.
+                ///
+                /// ```rust
+                /// let socket_io = server.socket_io();
+                /// socket_io.add_ns("/");
+                /// socket_io.emit("/", "message", json!("Hi"));
+                /// ```
+                pub fn socket_io(&self) -> SocketIO {
+                    self.config.socket_io.clone()
+                }
 
-/// A web server (handling both `HTTP` and `socket.io` requests)
-pub struct WebServer {
-    pub address: SocketAddr,
-}
+                /// Also listen on the `address`
+                pub fn with_address(mut self, address: SocketAddr) -> Self {
+                    self.binds.push(Bind::Tcp(address));
+                    self
+                }
 
-impl WebServer {
-    /// Create a new `WebServer` listening on the specified `port`.
-    ///
-    /// Currently only one instance should be created, because the `socket.io`
-    /// service is `static`: this means that all server instances would share the
-    /// same `socket.io` handling.
-    pub fn new(port: u16) -> Self {
-        let address = SocketAddr::from_str(&format!("0.0.0.0:{port}")).unwrap();
+                /// Also listen on a Unix domain socket at `path` (replacing the
+                /// socket left at `path` by a previous run, if any)
+                #[cfg(unix)]
+                pub fn with_unix_socket(mut self, path: impl AsRef<Path>) -> Self {
+                    self.binds.push(Bind::Unix(path.as_ref().to_path_buf()));
+                    self
+                }
 
-        WebServer { address }
-    }
+                /// Also accept the connections of an already bound TCP `listener`
+                pub fn with_listener(mut self, listener: std::net::TcpListener) -> Self {
+                    self.binds.push(Bind::TcpListener(listener));
+                    self
+                }
 
-    /// Start the server.
-    pub async fn start(&self) {
-        let listener = TcpListener::bind(self.address).await.unwrap();
-        let service = service_fn(handle_request);
+                /// Also accept the connections of an already bound Unix domain socket `listener`
+                #[cfg(unix)]
+                pub fn with_unix_listener(mut self, listener: std::os::unix::net::UnixListener) -> Self {
+                    self.binds.push(Bind::UnixListener(listener));
+                    self
+                }
 
-        #[cfg(debug_assertions)]
-        println!("> Server running at http://{}", self.address);
+                /// Also accept the connections of the sockets passed by the service
+                /// manager with socket activation (`LISTEN_FDS`, e.g. by a systemd
+                /// `.socket` unit). The sockets are ignored if they are meant for
+                /// another process, and can only be taken once.
+                #[cfg(unix)]
+                pub fn with_listen_fds(mut self) -> Self {
+                    use std::os::fd::{FromRawFd, IntoRawFd};
+
+                    /// First file descriptor passed by the service manager
+                    const LISTEN_FDS_START: i32 = 3;
+                    static TAKEN: AtomicBool = AtomicBool::new(false);
+
+                    let var = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u32>().ok());
+
+                    if var("LISTEN_PID") != Some(std::process::id()) || TAKEN.swap(true, Ordering::SeqCst) {
+                        return self;
+                    }
+
+                    let count = var("LISTEN_FDS").unwrap_or(0) as i32;
+
+                    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+                        // SAFETY: the service manager passes the ownership of the sockets
+                        // `LISTEN_FDS_START..LISTEN_FDS_START + LISTEN_FDS` to the process,
+                        // and `TAKEN` ensures they are owned only once
+                        let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+
+                        // Unix domain sockets don't have an IP address
+                        if listener.local_addr().is_ok() {
+                            self.binds.push(Bind::TcpListener(listener));
+                        } else {
+                            // SAFETY: the file descriptor is released by the `TcpListener`
+                            let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(listener.into_raw_fd()) };
+                            self.binds.push(Bind::UnixListener(listener));
+                        }
+                    }
+
+                    self
+                }
 
-        loop {
-            let (stream, _) = listener.accept().await.unwrap();
-            let io = TokioIo::new(stream);
+                /// Apply the `cors` policy to the responses
+                pub fn with_cors(mut self, cors: CorsPolicy) -> Self {
+                    self.config.cors = Some(cors);
+                    self
+                }
 
-            tokio::task::spawn(async move {
-                if let Err(e) = http1::Builder::new()
-                    .serve_connection(io, service.clone())
-                    .with_upgrades()
-                    .await
-                {
+                /// Don't set any CORS headers on the responses
+                pub fn without_cors(mut self) -> Self {
+                    self.config.cors = None;
+                    self
+                }
+
+                /// Limit the size of the request bodies to `bytes`, answering
+                /// `413 Payload too large` to the requests exceeding it
+                /// (unlimited by default)
+                pub fn with_max_body_size(mut self, bytes: u64) -> Self {
+                    self.config.max_body_size = Some(bytes);
+                    self
+                }
+
+                /// Keep the HTTP/1.1 connections alive between requests (enabled by default)
+                pub fn with_keep_alive(mut self, enabled: bool) -> Self {
+                    self.config.keep_alive = enabled;
+                    self
+                }
+
+                /// Limit the number of concurrent streams (requests) of each
+                /// HTTP/2 connection (unlimited by default)
+                pub fn with_max_concurrent_streams(mut self, max: u32) -> Self {
+                    self.config.max_concurrent_streams = Some(max);
+                    self
+                }
+
+                /// Ping the HTTP/2 clients every `interval`, closing the connections
+                /// not acknowledging a ping within `timeout` (disabled by default)
+                pub fn with_http2_keep_alive(mut self, interval: Duration, timeout: Duration) -> Self {
+                    self.config.http2_keep_alive = Some((interval, timeout));
+                    self
+                }
+
+                /// Give the open connections up to `timeout` to finish their requests
+                /// when the server shuts down (30 seconds by default)
+                pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+                    self.config.shutdown_timeout = timeout;
+                    self
+                }
+
+                /// Limit the number of open connections to `max`: once reached, the new
+                /// connections wait (in the listeners backlog) until some are closed
+                /// (unlimited by default)
+                pub fn with_max_connections(mut self, max: usize) -> Self {
+                    self.config.max_connections = Some(max);
+                    self
+                }
+
+                /// Close the HTTP/1.1 connections not sending the headers of a request
+                /// within `timeout`, the new connections (HTTP/1.1 or HTTP/2) not sending
+                /// their first request within it, and the TLS connections not completing
+                /// the handshake within it (30 seconds by default)
+                pub fn with_header_read_timeout(mut self, timeout: Duration) -> Self {
+                    self.config.header_read_timeout = timeout;
+                    self
+                }
+
+                /// Close the connections not sending any request for `timeout`
+                /// (disabled by default)
+                pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+                    self.config.idle_timeout = Some(timeout);
+                    self
+                }
+
+                /// Answer `504 Gateway timeout` to the requests whose handler doesn't
+                /// answer within `timeout`, reading of the request body included
+                /// (unlimited by default). Streamed response bodies and `socket.io`
+                /// requests aren't limited.
+                pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+                    self.config.request_timeout = Some(timeout);
+                    self
+                }
+
+                /// Start the server, until the process receives `SIGINT` (Ctrl+C)
+                /// or `SIGTERM` (see `start_with_shutdown`).
+                ///
+                /// Connections speak HTTP/1.1 or HTTP/2 with prior knowledge (h2c),
+                /// detected from their first bytes. `socket.io` WebSocket upgrades
+                /// are supported on HTTP/1.1 connections.
+                pub async fn start(&self) -> io::Result<()> {
+                    self.start_with_shutdown(shutdown_signal()).await
+                }
+
+                /// Start the server, until the `signal` future completes.
+                ///
+                /// The server then stops accepting connections, disconnects the
+                /// `socket.io` clients and lets the open connections finish their
+                /// requests (closing the idle ones) within the shutdown timeout
+                /// (see `with_shutdown_timeout`), before returning.
+                ///
+                /// ```rust
+                /// let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+                /// tokio::spawn(server.start_with_shutdown(async { rx.await.ok(); }));
+                /// ...
+                /// tx.send(()).ok();
+                /// ```
+                pub async fn start_with_shutdown(&self, signal: impl Future<Output = ()>) -> io::Result<()> {
+                    if self.binds.is_empty() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, "No address to listen on"));
+                    }
+
+                    if let Some(cors) = &self.config.cors {
+                        cors.check()?;
+                    }
+
+                    let mut listeners = vec![];
+
+                    for bind in &self.binds {
+                        listeners.push(bind.listen().await?);
+                    }
+
+                    let builder = Arc::new(self.config.connection_builder());
+                    let config = Arc::new(self.config.clone());
+                    let (shutdown_tx, shutdown_rx) = watch::channel(());
+                    #tls_setup
+
+                    #[cfg(debug_assertions)]
+                    for listener in &listeners {
+                        println!("> Server running at {}", listener.url(scheme));
+                    }
+
+                    tokio::pin!(signal);
+
+                    let connections = self.config.max_connections.map(|max| Arc::new(Semaphore::new(max)));
+                    let mut accept_backoff: Option<Duration> = None;
+
+                    // The connections tasks, aborted if still running after the shutdown timeout
+                    let mut tasks = tokio::task::JoinSet::new();
+
+                    loop {
+                        // Free the finished tasks
+                        while tasks.try_join_next().is_some() {}
+
+                        let permit = match &connections {
+                            Some(connections) => tokio::select! {
+                                permit = connections.clone().acquire_owned() => permit.ok(),
+                                _ = &mut signal => break,
+                            },
+                            None => None,
+                        };
+
+                        let accept = future::select_all(listeners.iter().map(|l| Box::pin(l.accept())));
+                        let accepted = tokio::select! {
+                            (accepted, _, _) = accept => accepted,
+                            _ = &mut signal => break,
+                        };
+
+                        let stream = match accepted {
+                            Ok(stream) => {
+                                accept_backoff = None;
+                                Connection { permit, ..stream }
+                            }
+                            Err(e) if is_connection_error(&e) => continue,
+                            Err(e) => {
+                                // e.g. too many open files: wait for some connections to be closed
+                                let delay = accept_backoff.map_or(ACCEPT_BACKOFF_MIN, |d| (d * 2).min(ACCEPT_BACKOFF_MAX));
+                                accept_backoff = Some(delay);
+                                eprintln!("Accept error: {e} (retrying in {delay:?})");
+
+                                tokio::select! {
+                                    _ = tokio::time::sleep(delay) => continue,
+                                    _ = &mut signal => break,
+                                }
+                            }
+                        };
+                        let builder = builder.clone();
+                        let config = config.clone();
+                        let shutdown = shutdown_rx.clone();
+
+                        #serve
+                    }
+
+                    #[cfg(debug_assertions)]
+                    println!("> Server shutting down");
+
+                    drop(listeners);
+                    shutdown_tx.send(()).ok();
+                    self.config.socket_io.close().await;
+
+                    let drained = tokio::time::timeout(self.config.shutdown_timeout, async {
+                        while tasks.join_next().await.is_some() {}
+                    });
+
+                    if drained.await.is_err() {
+                        eprintln!("Shutdown timeout elapsed, dropping the open connections");
+                        tasks.abort_all();
+
+                        while tasks.join_next().await.is_some() {}
+                    }
+
+                    Ok(())
+                }
+            }
+
+            /// First and maximum delays before accepting again after an accept error
+            const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+            const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+            /// Whether the accept `error` only concerns the connection being
+            /// accepted (e.g. reset by the client), rather than the listener
+            fn is_connection_error(error: &io::Error) -> bool {
+                matches!(
+                    error.kind(),
+                    io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset
+                )
+            }
+
+            /// Complete when the process receives `SIGINT` (Ctrl+C) or `SIGTERM`
+            async fn shutdown_signal() {
+                let ctrl_c = async {
+                    if tokio::signal::ctrl_c().await.is_err() {
+                        std::future::pending::<()>().await
+                    }
+                };
+
+                #[cfg(unix)]
+                let terminate = async {
+                    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+                        Ok(mut terminate) => {
+                            terminate.recv().await;
+                        }
+                        Err(_) => std::future::pending::<()>().await,
+                    }
+                };
+
+                #[cfg(not(unix))]
+                let terminate = std::future::pending::<()>();
+
+                tokio::select! {
+                    _ = ctrl_c => {}
+                    _ = terminate => {}
+                }
+            }
+
+            /// The requests activity of a connection, to close it once idle
+            struct ConnectionActivity {
+                /// Number of requests being handled
+                requests: AtomicUsize,
+
+                /// When the last request was received or answered
+                last: Mutex<Instant>,
+
+                /// Whether a request has been received
+                received: AtomicBool,
+            }
+
+            impl ConnectionActivity {
+                fn new() -> Self {
+                    ConnectionActivity {
+                        requests: AtomicUsize::new(0),
+                        last: Mutex::new(Instant::now()),
+                        received: AtomicBool::new(false),
+                    }
+                }
+
+                /// Track a request until the returned guard is dropped
+                fn request(self: &Arc<Self>) -> ActiveRequest {
+                    self.requests.fetch_add(1, Ordering::SeqCst);
+                    self.received.store(true, Ordering::SeqCst);
+                    *self.last.lock().unwrap() = Instant::now();
+                    ActiveRequest(self.clone())
+                }
+
+                /// Complete if no request has been received within `timeout`
+                async fn no_request(&self, timeout: Duration) {
+                    tokio::time::sleep(timeout).await;
+
+                    if self.received.load(Ordering::SeqCst) {
+                        std::future::pending::<()>().await
+                    }
+                }
+
+                /// Complete once no request has been handled for `timeout`
+                async fn idle(&self, timeout: Option<Duration>) {
+                    let Some(timeout) = timeout else {
+                        return std::future::pending().await;
+                    };
+
+                    loop {
+                        let now = Instant::now();
+                        let deadline = *self.last.lock().unwrap() + timeout;
+
+                        match (self.requests.load(Ordering::SeqCst), deadline > now) {
+                            (0, false) => return,
+                            (_, true) => tokio::time::sleep_until(deadline).await,
+                            (_, false) => tokio::time::sleep(timeout).await,
+                        }
+                    }
+                }
+            }
+
+            /// A request being handled, tracked by its `ConnectionActivity`
+            struct ActiveRequest(Arc<ConnectionActivity>);
+
+            impl Drop for ActiveRequest {
+                fn drop(&mut self) {
+                    *self.0.last.lock().unwrap() = Instant::now();
+                    self.0.requests.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+
+            /// Serve the requests of a connection `stream`, until it's closed or
+            /// (once its requests are answered) it's idle or the server is shutting down
+            async fn serve_connection<S>(
+                builder: Arc<auto::Builder<TokioExecutor>>,
+                stream: S,
+                config: Arc<ServerConfig>,
+                mut shutdown: watch::Receiver<()>,
+            ) where
+                S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+            {
+                let (idle_timeout, header_read_timeout) = (config.idle_timeout, config.header_read_timeout);
+                let activity = Arc::new(ConnectionActivity::new());
+                let service = {
+                    let activity = activity.clone();
+
+                    service_fn(move |req| {
+                        let request = activity.request();
+                        let response = handle_request(req, config.clone());
+
+                        async move {
+                            let response = response.await;
+                            drop(request);
+                            response
+                        }
+                    })
+                };
+                let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+                tokio::pin!(connection);
+
+                let result = tokio::select! {
+                    result = connection.as_mut() => result,
+                    _ = shutdown.changed() => {
+                        connection.as_mut().graceful_shutdown();
+                        connection.await
+                    }
+                    _ = activity.idle(idle_timeout) => {
+                        connection.as_mut().graceful_shutdown();
+                        connection.await
+                    }
+                    // The header read timeout only applies once the protocol is detected
+                    // from the first bytes, which could otherwise never be sent
+                    _ = activity.no_request(header_read_timeout) => {
+                        #[cfg(debug_assertions)]
+                        eprintln!("Server error: no request received within {header_read_timeout:?}");
+                        return;
+                    }
+                };
+
+                if let Err(e) = result {
                     #[cfg(debug_assertions)]
                     eprintln!("Server error: {e}")
                 }
-            });
-        }
-    }
-}
+            }
 
-/// A struct to access the `socket.io` methods
-pub struct SocketIO;
+            #tls_items
 
-impl SocketIO {
-    // TODO: add namespace handling
+            /// A handle to the `socket.io` service of a `WebServer` (see
+            /// `WebServer::socket_io`), cheap to clone and to move to other threads
+            #[derive(Clone)]
+            pub struct SocketIO {
+                service: SocketIOService,
+                io: SocketIo,
+                sockets: Arc<Mutex<HashMap<String, SocketRef>>>,
+            }
 
-    pub fn has_connections() -> bool {
-        !SOCKETS.lock().unwrap().is_empty()
-    }
+            impl SocketIO {
+                // TODO: add namespace handling
 
-    /// Create a given `namespace`, providing
-    /// default auth and disconnection handling
-    pub fn add_ns(namespace: &str) {
-        let namespace = namespace.to_string();
-        SOCKET_SERVICE.1.ns(
-            namespace,
-            |socket: SocketRef, Data(data): Data<Value>| async move {
-                #[cfg(debug_assertions)]
-                println!("`Socket.IO` connected: {:?} {:?}", socket.ns(), socket.id);
-                socket.emit("auth", &data).ok();
+                fn new() -> Self {
+                    let (service, io) = SocketIo::builder().req_path(SOCKET_IO_PATH).build_svc();
+
+                    SocketIO {
+                        service,
+                        io,
+                        sockets: Arc::new(Mutex::new(HashMap::new())),
+                    }
+                }
+
+                pub fn has_connections(&self) -> bool {
+                    !self.sockets.lock().unwrap().is_empty()
+                }
+
+                /// Create a given `namespace`, providing
+                /// default auth and disconnection handling
+                pub fn add_ns(&self, namespace: &str) {
+                    let namespace = namespace.to_string();
+                    let sockets = self.sockets.clone();
+
+                    self.io.ns(namespace, move |socket: SocketRef, Data(data): Data<Value>| async move {
+                        #[cfg(debug_assertions)]
+                        println!("`Socket.IO` connected: {:?} {:?}", socket.ns(), socket.id);
+                        socket.emit("auth", &data).ok();
+
+                        let disconnected = sockets.clone();
+                        socket.on_disconnect(move |socket: SocketRef, reason: DisconnectReason| async move {
+                            disconnected.lock().unwrap().remove(&socket.id.to_string());
+                            #[cfg(debug_assertions)]
+                            println!("Socket.IO disconnected: {} {}", socket.id, reason);
+                        });
+
+                        sockets.lock().unwrap().insert(socket.id.to_string(), socket);
+                    });
+                }
 
-                socket.on_disconnect(|socket: SocketRef, reason: DisconnectReason| async move {
-                    SOCKETS.lock().unwrap().remove(&socket.id.to_string());
+                /// Emit the given `data` on the specified `namespace` `topic`
+                pub fn emit(&self, namespace: &str, topic: &str, data: Value) {
                     #[cfg(debug_assertions)]
-                    println!("Socket.IO disconnected: {} {}", socket.id, reason);
-                });
+                    println!("Emitting on namespace {namespace} topic {topic} → {data}");
+
+                    let topic = topic.to_string();
+                    for socket in self.sockets.lock().unwrap().values() {
+                        if socket.ns() == namespace {
+                            socket.emit(topic.clone(), &data).ok();
+                        }
+                    }
+                }
 
-                SOCKETS
-                    .lock()
-                    .unwrap()
-                    .insert(socket.id.to_string(), socket);
-            },
-        );
-    }
+                async fn handle_request(&self, req: HyperRequest<Incoming>) -> Result<HyperResponse<HttpBody>, Infallible> {
+                    self.service
+                        .clone()
+                        .call(req)
+                        .await
+                        .map(|res| res.map(|body| body.map_err(io::Error::other).boxed_unsync()))
+                }
 
-    /// Emit the given `data` on the specified `namespace` `topic`
-    pub fn emit(namespace: &str, topic: &str, data: Value) {
-        #[cfg(debug_assertions)]
-        println!("Emitting on namespace {namespace} topic {topic} → {data}");
+                /// Disconnect all the clients
+                async fn close(&self) {
+                    self.io.close().await;
+                }
+            }
 
-        let topic = topic.to_string();
-        for socket in SOCKETS.lock().unwrap().values() {
-            if socket.ns() == namespace {
-                socket.emit(topic.clone(), &data).ok();
+            /// Create a json response
+            macro_rules! json_response {
+                (
+                    $code:expr,
+                    $body:tt$(,)?
+                ) => {
+                    $crate::Response::from_string(
+                        $code,
+                        Some($crate::Headers::from_static(&[("content-type", "application/json")])),
+                        Some(&serde_json::json!($body).to_string()),
+                    )
+                };
             }
+
+            pub(crate) use json_response;
         }
+
+        pub use self::__next_rs::{
+            Body, BodyError, CorsPolicy, DynField, ErrorInfo, Headers, IntoResponse, InvalidHeader, Json, Next,
+            NextError, QueryParams, ReqMethod, Request, Response, ResponseBody, ResponseBuilder, SocketIO, SseEvent, WebServer,
+        };
+        #tls_exports
+        pub(crate) use self::__next_rs::json_response;
     }
 }
 
-/// Create a json response
-macro_rules! json_response {
-    (
-        $code:expr,
-        $body:tt$(,)?
-    ) => {
-        Response::from_string(
-            $code,
-            Some("Content-Type=application/json"),
-            Some(&serde_json::json!($body).to_string()),
-        )
-    };
+/// The HTTPS support of the `WebServer`, generated if the `tls` argument is set
+struct Tls {
+    /// The TLS configuration and certificates types
+    items: TokenStream,
+
+    /// The `WebServer` field storing the TLS configuration, and its initializer
+    field: TokenStream,
+    init: TokenStream,
+
+    /// Statements preparing the `TlsAcceptor` in `WebServer::start`
+    setup: TokenStream,
+
+    /// Statement serving an accepted `stream` (possibly over TLS)
+    serve: TokenStream,
+    exports: TokenStream,
 }
 
-pub(crate) use json_response;
+impl Tls {
+    fn new(config: &Config) -> Self {
+        if !config.tls {
+            return Tls {
+                items: quote! {},
+                field: quote! {},
+                init: quote! {},
+                setup: quote! { let scheme = "http"; },
+                serve: quote! { tasks.spawn(serve_connection(builder, stream, config, shutdown)); },
+                exports: quote! {},
+            };
+        }
+
+        let items = quote! {
+            use std::sync::RwLock;
+            use tokio_rustls::{
+                rustls::{
+                    self,
+                    crypto::CryptoProvider,
+                    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+                    server::{ClientHello, ResolvesServerCert},
+                    sign::CertifiedKey,
+                },
+                TlsAcceptor,
+            };
+
+            /// The crypto provider installed for the process, `ring` otherwise
+            fn crypto_provider() -> Arc<CryptoProvider> {
+                CryptoProvider::get_default()
+                    .cloned()
+                    .unwrap_or_else(|| Arc::new(rustls::crypto::ring::default_provider()))
+            }
+
+            /// A certificate chain and its private key, loaded from PEM files
+            #[derive(Debug)]
+            struct TlsCert {
+                cert_path: PathBuf,
+                key_path: PathBuf,
+
+                /// Modification times of the files when they were last loaded
+                modified: Mutex<(Option<SystemTime>, Option<SystemTime>)>,
+                key: RwLock<Arc<CertifiedKey>>,
+            }
+
+            impl TlsCert {
+                fn load(cert_path: &Path, key_path: &Path) -> io::Result<Self> {
+                    Ok(TlsCert {
+                        cert_path: cert_path.to_path_buf(),
+                        key_path: key_path.to_path_buf(),
+                        modified: Mutex::new(Self::modified(cert_path, key_path)),
+                        key: RwLock::new(Arc::new(Self::certified_key(cert_path, key_path)?)),
+                    })
+                }
+
+                fn modified(cert_path: &Path, key_path: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+                    let modified = |path: &Path| metadata(path).and_then(|m| m.modified()).ok();
+                    (modified(cert_path), modified(key_path))
+                }
+
+                fn certified_key(cert_path: &Path, key_path: &Path) -> io::Result<CertifiedKey> {
+                    let invalid = |path: &Path, e: &dyn Display| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Invalid TLS file {}: {e}", path.display()),
+                        )
+                    };
+
+                    let certs = CertificateDer::pem_file_iter(cert_path)
+                        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                        .map_err(|e| invalid(cert_path, &e))?;
+                    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| invalid(key_path, &e))?;
+
+                    CertifiedKey::from_der(certs, key, &crypto_provider()).map_err(|e| invalid(key_path, &e))
+                }
+
+                /// Reload the files if they changed since they were last loaded,
+                /// keeping the current certificate if they are invalid
+                fn reload(&self) {
+                    let modified = Self::modified(&self.cert_path, &self.key_path);
+                    let mut loaded = self.modified.lock().unwrap();
+
+                    if *loaded == modified {
+                        return;
+                    }
+
+                    *loaded = modified;
+
+                    match Self::certified_key(&self.cert_path, &self.key_path) {
+                        Ok(key) => *self.key.write().unwrap() = Arc::new(key),
+                        Err(e) => eprintln!("Cannot reload the TLS certificate: {e}"),
+                    }
+                }
+
+                fn get(&self) -> Arc<CertifiedKey> {
+                    self.key.read().unwrap().clone()
+                }
+            }
+
+            /// Resolve the certificate of a TLS connection from its SNI server name
+            #[derive(Debug)]
+            struct CertResolver {
+                default: TlsCert,
+                by_name: RwLock<HashMap<String, Arc<TlsCert>>>,
+            }
+
+            impl CertResolver {
+                fn reload(&self) {
+                    self.default.reload();
+                    self.by_name.read().unwrap().values().for_each(|cert| cert.reload());
+                }
+            }
+
+            impl ResolvesServerCert for CertResolver {
+                fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+                    let by_name = self.by_name.read().unwrap();
+                    let cert = client_hello
+                        .server_name()
+                        .and_then(|name| by_name.get(&name.to_ascii_lowercase()));
+
+                    Some(cert.map_or_else(|| self.default.get(), |cert| cert.get()))
+                }
+            }
+
+            /// The HTTPS configuration of a `WebServer`: a default certificate,
+            /// optional certificates selected by SNI server name, and their
+            /// reloading when the files change.
+            ///
+            /// ```rust
+            /// let tls = TlsConfig::new("certs/default.pem", "certs/default.key")?
+            ///     .with_sni("admin.example.com", "certs/admin.pem", "certs/admin.key")?
+            ///     .with_reload(Duration::from_secs(60));
+            ///
+            /// WebServer::new(443).with_tls_config(tls).start().await;
+            /// ```
+            #[derive(Clone)]
+            pub struct TlsConfig {
+                certs: Arc<CertResolver>,
+                server_config: Arc<rustls::ServerConfig>,
+                reload_interval: Option<Duration>,
+            }
+
+            impl TlsConfig {
+                /// Serve the certificate chain and the private key of the PEM files
+                /// at `cert_path` and `key_path`
+                pub fn new(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> io::Result<Self> {
+                    let certs = Arc::new(CertResolver {
+                        default: TlsCert::load(cert_path.as_ref(), key_path.as_ref())?,
+                        by_name: RwLock::new(HashMap::new()),
+                    });
+
+                    let mut server_config = rustls::ServerConfig::builder_with_provider(crypto_provider())
+                        .with_safe_default_protocol_versions()
+                        .map_err(io::Error::other)?
+                        .with_no_client_auth()
+                        .with_cert_resolver(certs.clone());
+                    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+                    Ok(TlsConfig {
+                        certs,
+                        server_config: Arc::new(server_config),
+                        reload_interval: None,
+                    })
+                }
+
+                /// Serve another certificate to the clients requesting the `server_name`
+                pub fn with_sni(
+                    self,
+                    server_name: &str,
+                    cert_path: impl AsRef<Path>,
+                    key_path: impl AsRef<Path>,
+                ) -> io::Result<Self> {
+                    let cert = TlsCert::load(cert_path.as_ref(), key_path.as_ref())?;
+
+                    self.certs
+                        .by_name
+                        .write()
+                        .unwrap()
+                        .insert(server_name.to_ascii_lowercase(), Arc::new(cert));
+
+                    Ok(self)
+                }
+
+                /// Check the certificate files every `interval`, reloading them when they
+                /// change (the new connections use the new certificates)
+                pub fn with_reload(mut self, interval: Duration) -> Self {
+                    self.reload_interval = Some(interval);
+                    self
+                }
+
+                fn acceptor(&self) -> TlsAcceptor {
+                    TlsAcceptor::from(self.server_config.clone())
+                }
 
-}; // <=
+                /// Spawn the task reloading the certificates (if enabled),
+                /// until the server is shutting down
+                fn spawn_reload(&self, mut shutdown: watch::Receiver<()>) {
+                    let Some(interval) = self.reload_interval else {
+                        return;
+                    };
+                    let certs = self.certs.clone();
+
+                    tokio::task::spawn(async move {
+                        let mut timer = tokio::time::interval(interval);
+                        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+                        loop {
+                            tokio::select! {
+                                _ = timer.tick() => certs.reload(),
+                                _ = shutdown.changed() => break,
+                            }
+                        }
+                    });
+                }
+            }
+
+            impl WebServer {
+                /// Serve HTTPS (for both the routes and `socket.io`) with the
+                /// certificate chain and the private key of the PEM files
+                /// at `cert_path` and `key_path`
+                pub fn with_tls(self, cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> io::Result<Self> {
+                    Ok(self.with_tls_config(TlsConfig::new(cert_path, key_path)?))
+                }
+
+                /// Serve HTTPS with the `tls` configuration
+                pub fn with_tls_config(mut self, tls: TlsConfig) -> Self {
+                    self.tls = Some(tls);
+                    self
+                }
+            }
+        };
+
+        Tls {
+            items,
+            field: quote! {
+                /// The HTTPS configuration (if enabled)
+                tls: Option<TlsConfig>,
+            },
+            init: quote! { tls: None, },
+            setup: quote! {
+                let acceptor = self.tls.as_ref().map(|tls| {
+                    tls.spawn_reload(shutdown_rx.clone());
+                    tls.acceptor()
+                });
+                let scheme = if acceptor.is_some() { "https" } else { "http" };
+            },
+            serve: quote! {
+                match acceptor.clone() {
+                    Some(acceptor) => {
+                        tasks.spawn(async move {
+                            let handshake = tokio::time::timeout(config.header_read_timeout, acceptor.accept(stream));
+
+                            match handshake.await {
+                                Ok(Ok(stream)) => serve_connection(builder, stream, config, shutdown).await,
+                                Ok(Err(e)) => {
+                                    #[cfg(debug_assertions)]
+                                    eprintln!("TLS handshake error: {e}")
+                                }
+                                Err(_) => {
+                                    #[cfg(debug_assertions)]
+                                    eprintln!("TLS handshake timed out")
+                                }
+                            }
+                        });
+                    }
+                    None => {
+                        tasks.spawn(serve_connection(builder, stream, config, shutdown));
+                    }
+                }
+            },
+            exports: quote! { pub use self::__next_rs::TlsConfig; },
+        }
+    }
+}
@@ -1,20 +1,44 @@
+mod config;
 mod defines;
 mod mime_type;
 mod route;
+mod route_table;
 
-use {defines::DEFINES, proc_macro::TokenStream, route::Route};
+use {
+    config::Config, defines::defines, proc_macro::TokenStream,
+    proc_macro2::TokenStream as TokenStream2, route::Route, route_table::RouteTable,
+    syn::parse_macro_input,
+};
+
+fn get_defines(base_route: Route, config: &Config) -> syn::Result<TokenStream2> {
+    let route_table = RouteTable::new(&base_route);
+    route_table.check(config)?;
 
-fn get_defines(base_route: Route) -> String {
     let modules = base_route.get_mod();
-    let handlers = base_route.get_handlers().join("\n");
+    let router = route_table.router();
+    let handlers = route_table.handlers();
+    let error_pages = route_table.error_pages();
 
-    DEFINES
-        .replace("$modules", &modules)
-        .replace("$handlers", &handlers)
+    Ok(defines(modules, router, handlers, error_pages, config))
 }
 
+/// Create the server components from the `routes` directory.
+///
+/// Accepts optional `key = value` arguments:
+/// - `routes`: path of the `routes` directory relative to the crate root
+///   (e.g. `"src/routes"`), also accepted as a leading string literal.
+///   If omitted, the crate is searched for a single `routes` folder
+/// - `socket_path`: path prefix of the `socket.io` requests (default `"/socket.io"`)
+/// - `cors`: whether the default CORS headers are set on responses (default `true`)
+/// - `static_max_bytes`: maximum size of the embedded static files (default unlimited)
+/// - `tls`: whether `WebServer::with_tls` is generated, serving HTTPS with
+///   `tokio-rustls` (default `false`)
 #[proc_macro]
-pub fn make_server(_: TokenStream) -> TokenStream {
-    let base_route = Route::base();
-    get_defines(base_route).parse().unwrap()
+pub fn make_server(input: TokenStream) -> TokenStream {
+    let config = parse_macro_input!(input as Config);
+
+    match Route::base(&config).and_then(|base_route| get_defines(base_route, &config)) {
+        Ok(defines) => defines.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
 }
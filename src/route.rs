@@ -1,144 +1,455 @@
-use super::mime_type::MimeType;
-use lazy_static::lazy_static;
+use super::{config::Config, mime_type::MimeType};
+use proc_macro2::{Ident, Span, TokenStream};
+use quote::{format_ident, quote};
 use std::{
-    cmp::Ordering,
+    env,
+    fmt::Display,
     fs::{metadata, read_dir, read_to_string},
-    process::{exit, Command},
+    path::{Path, PathBuf},
 };
+use syn::{Error, Fields, File, Item, ItemFn, Type, Visibility};
 
 static ROUTES_DIR: &str = "routes";
 
-lazy_static! {
-    static ref ACTUAL_ROUTES_PATH: String = String::from_utf8(
-        Command::new("sh")
-            .args(["-c", &format!("find . | grep \".*routes$\"")])
-            .output()
-            .unwrap()
-            .stdout,
-    )
-    .unwrap()
-    .trim()
-    .to_string();
-    static ref CALL_SITE_PATH: String = String::from_utf8(
-        Command::new("sh")
-            .args(["-c", "grep -Rw . -e \"make_server\\!\" | grep -v target"])
-            .output()
-            .unwrap()
-            .stdout,
-    )
-    .unwrap()
-    .split_once(".rs")
-    .unwrap()
-    .0
-    .rsplit_once('/')
-    .unwrap()
-    .0
-    .trim()
-    .to_string();
+/// Names that can't be used as identifiers, not even as raw ones
+static RESERVED_NAMES: [&str; 5] = ["_", "self", "super", "crate", "Self"];
+
+/// Directories skipped while looking for the `routes` folder
+static IGNORED_DIRS: [&str; 2] = ["target", "node_modules"];
+
+/// Catch-all handler, called for every method without a dedicated function
+static FALLBACK_HANDLER: &str = "handler";
+
+/// Per-method handlers a route module can export,
+/// paired with the `ReqMethod` variant they are dispatched on
+static METHOD_HANDLERS: [(&str, &str); 7] = [
+    ("get", "Get"),
+    ("head", "Head"),
+    ("post", "Post"),
+    ("put", "Put"),
+    ("patch", "Patch"),
+    ("delete", "Delete"),
+    ("options", "Options"),
+];
+
+/// Module wrapping the handlers of its directory (and of the nested ones)
+static MIDDLEWARE_FILE: &str = "_middleware.rs";
+
+/// Function exported by the middleware modules
+static MIDDLEWARE_FN: &str = "middleware";
+
+/// Name of the type of the route parameters, parsed before calling the handlers
+static PARAMS_TYPE: &str = "Params";
+
+/// Names (without extension) of the error pages of a directory,
+/// as static `.html` files or as `.rs` modules
+static ERROR_PAGES: [(&str, ErrorPage); 3] = [
+    ("_404", ErrorPage::NotFound),
+    ("_500", ErrorPage::ServerError),
+    ("_error", ErrorPage::Any),
+];
+
+/// Function exported by the error page modules
+static ERROR_PAGE_FN: &str = "render";
+
+/// Error responses rendered by an error page, in lookup order
+/// (a directory's `_404` or `_500` page is preferred over its `_error` one)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorPage {
+    NotFound,
+
+    /// `5xx` responses
+    ServerError,
+
+    /// Any `4xx` or `5xx` response
+    Any,
+}
+
+/// Shape of the `Params` type declared by a route module
+#[derive(Debug)]
+pub enum Params {
+    /// `pub struct Params { .. }`, with the fields named after the route segments
+    Struct(Vec<Ident>),
+
+    /// `pub struct Params(..)`, with a field for each route segment
+    TupleStruct(usize),
+
+    /// `pub type Params = (..)`, with an element for each route segment
+    Tuple(usize),
+
+    /// `pub type Params = ..`, for a route with a single segment
+    Single,
 }
 
 #[derive(Debug)]
 pub struct Route {
     path: String,
+    relative_path: String,
     children: Option<Vec<Route>>,
-    static_body: Option<String>,
     mime_type: Option<MimeType>,
+    exported_handlers: Vec<String>,
+
+    /// Exported handlers taking the route parameters as second argument
+    params_handlers: Vec<String>,
+    params: Option<Params>,
+    is_middleware: bool,
+    error_page: Option<ErrorPage>,
 }
 
 impl Route {
-    fn new(path: String) -> Self {
+    /// Scan the file or directory at `path`, at `relative_path` from the `routes` directory
+    pub fn new(path: String, relative_path: String, config: &Config) -> syn::Result<Self> {
         let mut children: Option<Vec<Route>> = None;
-        let mut static_body: Option<String> = None;
         let mut mime_type: Option<MimeType> = None;
+        let mut exported_handlers: Vec<String> = vec![];
+        let mut params_handlers: Vec<String> = vec![];
+        let mut params: Option<Params> = None;
+        let is_middleware = relative_path.ends_with(&format!("/{MIDDLEWARE_FILE}"));
+        let error_page = Self::get_error_page(&relative_path);
+
+        let md =
+            metadata(&path).map_err(|e| Self::error(config, format!("Cannot read {path}: {e}")))?;
+
+        if md.is_dir() {
+            children = Some(Self::get_children(&path, &relative_path, config)?);
+        } else if is_middleware {
+            if let Some(module) = Self::parse_module(&path, config)? {
+                if !Self::get_exported_fns(&module)
+                    .iter()
+                    .any(|f| f.sig.ident == MIDDLEWARE_FN)
+                {
+                    return Err(Self::error(
+                        config,
+                        format!("Middleware {path} must export `pub async fn {MIDDLEWARE_FN}`"),
+                    ));
+                }
+            }
+        } else if error_page.is_some() && path.ends_with(".rs") {
+            if let Some(module) = Self::parse_module(&path, config)? {
+                if !Self::get_exported_fns(&module)
+                    .iter()
+                    .any(|f| f.sig.ident == ERROR_PAGE_FN)
+                {
+                    return Err(Self::error(
+                        config,
+                        format!("Error page {path} must export `pub async fn {ERROR_PAGE_FN}`"),
+                    ));
+                }
+            }
+        } else if path.ends_with(".rs") {
+            if let Some(module) = Self::parse_module(&path, config)? {
+                let handlers = Self::get_exported_handlers(&module);
 
-        if !path.is_empty() && metadata(&path).unwrap().is_dir() {
-            children = Some(Self::get_children(&path));
-        } else if !path.ends_with(".rs") {
-            let relative_path = if path.contains(&*CALL_SITE_PATH) {
-                path.replace(&*CALL_SITE_PATH, "")
-                    .trim_start_matches('/')
-                    .to_string()
-            } else {
-                panic!("'routes' folder and the 'make_server!' macro call must be in the same directory if there are static files.");
-            };
+                exported_handlers = handlers.iter().map(|f| f.sig.ident.to_string()).collect();
+                params_handlers = handlers
+                    .iter()
+                    .filter(|f| f.sig.inputs.len() > 1)
+                    .map(|f| f.sig.ident.to_string())
+                    .collect();
+                params = Self::get_params(&module);
+            }
+        } else {
+            if let Some(max_bytes) = config.static_max_bytes() {
+                let size = md.len();
+
+                if size > max_bytes {
+                    return Err(Error::new(
+                        config.static_max_bytes.as_ref().unwrap().span(),
+                        format!("Static file {path} is {size} bytes, exceeding `static_max_bytes` ({max_bytes})"),
+                    ));
+                }
+            }
 
-            static_body = Some(format!("include_bytes!(\"{relative_path}\")",));
             mime_type = path
                 .rsplit_once('.')
-                .map_or(None, |(_, ext)| MimeType::from(ext));
+                .and_then(|(_, ext)| MimeType::from(ext));
         }
 
-        Route {
+        Ok(Route {
             path,
+            relative_path,
             children,
-            static_body,
             mime_type,
+            exported_handlers,
+            params_handlers,
+            params,
+            is_middleware,
+            error_page,
+        })
+    }
+
+    /// Get the kind of error page of the file at `relative_path` (if any)
+    fn get_error_page(relative_path: &str) -> Option<ErrorPage> {
+        let name = relative_path.rsplit('/').next()?;
+        let (stem, ext) = name.rsplit_once('.')?;
+
+        if ext != "rs" && ext != "html" {
+            return None;
         }
+
+        ERROR_PAGES
+            .iter()
+            .find(|(page, _)| *page == stem)
+            .map(|(_, kind)| *kind)
     }
 
-    pub fn base() -> Self {
-        Self::check_is_dir(&*ACTUAL_ROUTES_PATH);
-        Self::new(ACTUAL_ROUTES_PATH.clone())
+    /// Parse the module at `path`.
+    ///
+    /// A module that can't be parsed exports no handlers: it is still declared,
+    /// so that the compiler reports the syntax errors in the offending file.
+    fn parse_module(path: &str, config: &Config) -> syn::Result<Option<File>> {
+        let content = read_to_string(path)
+            .map_err(|e| Self::error(config, format!("Cannot read {path}: {e}")))?;
+
+        Ok(syn::parse_file(&content).ok())
     }
 
-    fn check_is_dir(path: &str) {
-        let invalid_path_err = || {
-            println!("Path {path} is not a directory");
-            exit(-1);
-        };
+    /// Get the async functions exported by the `module`
+    fn get_exported_fns(module: &File) -> Vec<&ItemFn> {
+        module
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Fn(f)
+                    if matches!(f.vis, Visibility::Public(_)) && f.sig.asyncness.is_some() =>
+                {
+                    Some(f)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Get the handler functions exported by the `module`
+    fn get_exported_handlers(module: &File) -> Vec<&ItemFn> {
+        Self::get_exported_fns(module)
+            .into_iter()
+            .filter(|f| {
+                let name = f.sig.ident.to_string();
+                name == FALLBACK_HANDLER || METHOD_HANDLERS.iter().any(|(f, _)| *f == name)
+            })
+            .collect()
+    }
 
-        match metadata(path) {
-            Ok(md) => {
-                if !md.is_dir() {
-                    invalid_path_err()
+    /// Get the shape of the `Params` type exported by the `module` (if any)
+    fn get_params(module: &File) -> Option<Params> {
+        module.items.iter().find_map(|item| match item {
+            Item::Struct(s) if s.ident == PARAMS_TYPE && matches!(s.vis, Visibility::Public(_)) => {
+                Some(match &s.fields {
+                    Fields::Named(fields) => Params::Struct(
+                        fields
+                            .named
+                            .iter()
+                            .filter_map(|f| f.ident.clone())
+                            .collect(),
+                    ),
+                    Fields::Unnamed(fields) => Params::TupleStruct(fields.unnamed.len()),
+                    Fields::Unit => Params::TupleStruct(0),
+                })
+            }
+            Item::Type(t) if t.ident == PARAMS_TYPE && matches!(t.vis, Visibility::Public(_)) => {
+                Some(match &*t.ty {
+                    Type::Tuple(tuple) => Params::Tuple(tuple.elems.len()),
+                    _ => Params::Single,
+                })
+            }
+            _ => None,
+        })
+    }
+
+    /// Create a route scanning error, pointing at the `routes` argument (if any)
+    fn error(config: &Config, message: impl Display) -> Error {
+        Error::new(config.span(), message)
+    }
+
+    /// Add `error` to the `errors` reported together
+    fn push_error(errors: &mut Option<Error>, error: Error) {
+        match errors {
+            Some(errors) => errors.combine(error),
+            None => *errors = Some(error),
+        }
+    }
+
+    /// Create the base route from the `routes` directory.
+    ///
+    /// The directory is resolved relative to `CARGO_MANIFEST_DIR`: if no
+    /// `routes` argument is given, the crate is searched for a single `routes` folder.
+    pub fn base(config: &Config) -> syn::Result<Self> {
+        let manifest_dir = env::var("CARGO_MANIFEST_DIR").map_err(|_| {
+            Self::error(
+                config,
+                "`CARGO_MANIFEST_DIR` is not set: `make_server!` must be built by cargo",
+            )
+        })?;
+        let manifest_dir = PathBuf::from(manifest_dir);
+
+        let routes_path = match &config.routes {
+            Some(path) => {
+                let routes_path = manifest_dir.join(path.value());
+
+                if !routes_path.is_dir() {
+                    return Err(Error::new(
+                        path.span(),
+                        format!("Path {} is not a directory", routes_path.display()),
+                    ));
                 }
+
+                routes_path
+            }
+            None => Self::find_routes_dir(&manifest_dir)?,
+        };
+
+        let routes_path = routes_path.into_os_string().into_string().map_err(|path| {
+            Self::error(
+                config,
+                format!("Path {} is not valid UTF-8", path.to_string_lossy()),
+            )
+        })?;
+
+        Self::new(routes_path, String::new(), config)
+    }
+
+    /// Look for the only `routes` directory in the crate at `manifest_dir`
+    fn find_routes_dir(manifest_dir: &Path) -> syn::Result<PathBuf> {
+        let mut found: Vec<PathBuf> = vec![];
+        Self::collect_routes_dirs(manifest_dir, &mut found);
+
+        match found.len() {
+            1 => Ok(found.remove(0)),
+            0 => Err(Error::new(
+                proc_macro2::Span::call_site(),
+                format!(
+                    "No `{ROUTES_DIR}` directory found in {}, specify its path relative to the crate root: `make_server!(\"src/{ROUTES_DIR}\")`",
+                    manifest_dir.display()
+                ),
+            )),
+            _ => Err(Error::new(
+                proc_macro2::Span::call_site(),
+                format!(
+                    "Multiple `{ROUTES_DIR}` directories found ({}), specify the one to use relative to the crate root: `make_server!(\"src/{ROUTES_DIR}\")`",
+                    found
+                        .iter()
+                        .map(|p| p.strip_prefix(manifest_dir).unwrap_or(p).display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            )),
+        }
+    }
+
+    fn collect_routes_dirs(dir: &Path, found: &mut Vec<PathBuf>) {
+        let Ok(entries) = read_dir(dir) else {
+            return;
+        };
+
+        // Symlinks aren't followed, as they could loop back to a parent
+        let mut dirs = entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+            .map(|e| e.path())
+            .collect::<Vec<_>>();
+        dirs.sort();
+
+        for dir in dirs {
+            let name = dir.file_name().map(|n| n.to_string_lossy().to_string());
+
+            match name.as_deref() {
+                Some(name) if name == ROUTES_DIR => found.push(dir),
+                Some(name) if name.starts_with('.') || IGNORED_DIRS.contains(&name) => {}
+                _ => Self::collect_routes_dirs(&dir, found),
             }
-            _ => invalid_path_err(),
         }
     }
 
-    fn get_children(base_path: &str) -> Vec<Self> {
-        Self::check_is_dir(base_path);
+    fn get_children(
+        base_path: &str,
+        base_relative_path: &str,
+        config: &Config,
+    ) -> syn::Result<Vec<Self>> {
+        let entries = read_dir(base_path)
+            .map_err(|e| Self::error(config, format!("Cannot read {base_path}: {e}")))?;
+
+        let mut children: Vec<Self> = vec![];
+        let mut errors: Option<Error> = None;
+
+        for entry in entries {
+            let child = entry
+                .map_err(|e| Self::error(config, format!("Cannot read {base_path}: {e}")))
+                .and_then(|entry| {
+                    let name = entry.file_name().into_string().map_err(|name| {
+                        Self::error(
+                            config,
+                            format!(
+                                "File name {:?} in {base_path} is not valid UTF-8",
+                                name.to_string_lossy()
+                            ),
+                        )
+                    })?;
+
+                    Self::new(
+                        format!("{base_path}/{name}"),
+                        format!("{base_relative_path}/{name}"),
+                        config,
+                    )
+                });
+
+            match child {
+                Ok(child) => children.push(child),
+                Err(e) => Self::push_error(&mut errors, e),
+            }
+        }
 
-        read_dir(base_path)
-            .unwrap()
-            .map(|e| {
-                let entry = e.unwrap();
-                let entry_path = entry.path();
-                let path = String::from(entry_path.to_str().unwrap());
+        children.sort_by(|c1, c2| c1.path.cmp(&c2.path));
+
+        for (i, child) in children.iter().enumerate() {
+            let mod_name = child.mod_name();
+
+            if let Some(other) = children[..i].iter().find(|c| c.mod_name() == mod_name) {
+                Self::push_error(
+                    &mut errors,
+                    Self::error(
+                        config,
+                        format!(
+                            "Conflicting routes: {} and {} both define the module `{mod_name}`",
+                            other.path, child.path
+                        ),
+                    ),
+                );
+            }
+        }
 
-                Self::new(path)
-            })
-            .collect()
+        match errors {
+            Some(errors) => Err(errors),
+            None => Ok(children),
+        }
     }
 
-    fn clean_path(&self) -> String {
-        let path = String::from(ROUTES_DIR) + self.path.split(ROUTES_DIR).collect::<Vec<_>>()[1];
+    /// Names of the files and directories leading to the route, from the `routes` directory
+    fn segments(&self) -> Vec<&str> {
+        self.relative_path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
 
-        path.replace("/mod.rs", "/r#mod.rs")
-            .replace(".rs", "")
-            .replace("/super", "/_super")
-            .trim_matches('/')
-            .to_string()
+    pub fn path(&self) -> &str {
+        &self.path
     }
 
-    fn route_matcher(&self) -> String {
-        let clean_path = format!(
-            "\"{}\"",
-            self.clean_path()
-                .replacen(ROUTES_DIR, "", 1)
-                .replace("r#mod", "")
-                .replace("/_super", "/super")
-                .replace("index.html", "")
-                .trim_matches('/')
-                .to_string()
-        );
+    /// Path the route is served at
+    pub fn route_path(&self) -> String {
+        let mut segments = self.segments();
 
-        if !clean_path.contains("/__") {
-            return clean_path;
+        if let Some(last) = segments.pop() {
+            match last.strip_suffix(".rs") {
+                Some("mod") => {}
+                Some(name) => segments.push(name),
+                None if last == "index.html" => {}
+                None => segments.push(last),
+            }
         }
 
-        format!("path if matches_dynamic_route(path, {clean_path}, &mut req)")
+        segments.join("/")
     }
 
     fn is_mod(&self) -> bool {
@@ -147,127 +458,270 @@ impl Route {
                 && self.children.as_ref().unwrap().iter().any(|c| c.is_mod()))
     }
 
-    fn has_handler(&self) -> bool {
-        metadata(&self.path).unwrap().is_file() && {
-            let content = read_to_string(&self.path).unwrap();
-            content.contains("pub async fn handler<")
-        }
+    fn exports(&self, handler: &str) -> bool {
+        self.exported_handlers.iter().any(|h| h == handler)
     }
 
-    fn is_api(&self) -> bool {
-        self.is_mod() && self.has_handler()
+    pub fn params(&self) -> Option<&Params> {
+        self.params.as_ref()
     }
 
-    fn is_static(&self) -> bool {
-        self.static_body.is_some()
+    /// Get the exported handlers taking the route parameters
+    pub fn params_handlers(&self) -> &[String] {
+        &self.params_handlers
     }
 
-    fn clean_name(&self, name: &str) -> String {
-        name.replace(".", "_")
-            .replace("-", "_")
-            .replace(",", "_")
-            .replace(";", "_")
+    /// Path of the `Params` type of the module
+    pub fn params_type(&self) -> TokenStream {
+        let mod_path = self.mod_path();
+        let params = format_ident!("{PARAMS_TYPE}");
+
+        quote! { crate::#(#mod_path)::*::#params }
     }
 
-    fn handler(&self) -> Option<String> {
-        let mut handler = format!("{} => ", self.route_matcher());
+    /// Arguments passed to the exported `handler`
+    fn handler_args(&self, handler: &str) -> TokenStream {
+        if self.params_handlers.iter().any(|h| h == handler) {
+            quote! { req, params }
+        } else {
+            quote! { req }
+        }
+    }
 
-        let mod_path = format!(
-            "{}::",
-            self.clean_name(&self.clean_path().split("/").collect::<Vec<_>>().join("::"))
-        );
+    /// Path of the `middleware` function of a middleware module
+    pub fn middleware(&self) -> TokenStream {
+        let mod_path = self.mod_path();
+        let middleware = format_ident!("{MIDDLEWARE_FN}");
 
-        if self.is_api() {
-            handler += &format!("{mod_path}handler(req).await");
-        } else if self.is_static() {
-            handler += &format!(
-                "Ok(Response {{
-                    code: 200,
-                    headers: Some({mod_path}HEADERS.to_vec()),
-                    body: Some({mod_path}BODY.to_vec())
-                }})"
-            );
+        quote! { crate::#(#mod_path)::*::#middleware }
+    }
+
+    pub fn error_page(&self) -> Option<ErrorPage> {
+        self.error_page
+    }
+
+    /// Whether the route is a static HTML page
+    pub fn is_html(&self) -> bool {
+        self.is_static()
+            && self
+                .mime_type
+                .as_ref()
+                .is_some_and(|mime_type| mime_type.get().trim() == "text/html")
+    }
+
+    /// Render the error page from the `error: ErrorInfo` in scope
+    pub fn error_renderer(&self) -> TokenStream {
+        let mod_path = self.mod_path();
+
+        if self.is_static() {
+            quote! {
+                static_error_page(
+                    error,
+                    crate::#(#mod_path)::*::CONTENT_TYPE,
+                    crate::#(#mod_path)::*::BODY,
+                )
+            }
         } else {
-            return None;
+            let render = format_ident!("{ERROR_PAGE_FN}");
+            quote! { IntoResponse::into_response(crate::#(#mod_path)::*::#render(error).await) }
+        }
+    }
+
+    fn is_api(&self) -> bool {
+        self.is_mod() && !self.exported_handlers.is_empty()
+    }
+
+    pub fn is_static(&self) -> bool {
+        self.children.is_none() && !self.path.ends_with(".rs")
+    }
+
+    /// Turn a file or directory `name` into a valid module identifier
+    fn clean_name(name: &str) -> Ident {
+        let mut name = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+
+        if name.starts_with(|c: char| c.is_ascii_digit()) || RESERVED_NAMES.contains(&&*name) {
+            name.insert(0, '_');
         }
 
-        Some(handler + ",")
+        if syn::parse_str::<Ident>(&name).is_ok() {
+            Ident::new(&name, Span::call_site())
+        } else {
+            Ident::new_raw(&name, Span::call_site())
+        }
     }
 
-    fn mod_path(&self) -> String {
-        self.path
-            .rsplit_once("/")
-            .unwrap_or(("", &self.path))
-            .1
-            .to_string()
+    /// Module identifiers leading to the route, from the crate root
+    fn mod_path(&self) -> Vec<Ident> {
+        let mut mod_path = vec![Self::clean_name(ROUTES_DIR)];
+
+        mod_path.extend(self.segments().iter().map(|s| {
+            if self.children.is_none() {
+                Self::clean_name(s.strip_suffix(".rs").unwrap_or(s))
+            } else {
+                Self::clean_name(s)
+            }
+        }));
+
+        mod_path
     }
 
-    fn mod_name(&self) -> String {
-        let clean_path = self.clean_path();
-        self.clean_name(clean_path.rsplit_once("/").unwrap_or(("", &clean_path)).1)
+    fn mod_name(&self) -> Ident {
+        self.mod_path().pop().unwrap()
     }
 
-    pub fn get_mod(&self) -> String {
-        let mut mod_str = format!(
-            "#[path = \"{}\"]\npub mod {}",
-            self.mod_path(),
-            self.mod_name(),
-        );
+    /// Handle the request matched by the route
+    pub fn handler(&self) -> Option<TokenStream> {
+        let mod_path = self.mod_path();
 
-        if let Some(children) = &self.children {
-            let sub_mods = &children
-                .iter()
-                .map(|c| c.get_mod())
-                .collect::<Vec<_>>()
-                .join("\n");
-            mod_str += &format!(" {{{}}}", sub_mods);
+        if self.is_api() {
+            Some(self.api_handler(&mod_path))
         } else if self.is_static() {
-            mod_str += &format!(
-                "{{
-                    pub static HEADERS: &'static [u8] = b\"{}\";
-                    pub static BODY: &'static [u8] = {};
-                }}",
-                if let Some(mime_type) = &self.mime_type {
-                    format!("Content-Type={}", mime_type.get())
-                } else {
-                    String::new()
-                },
-                self.static_body.clone().unwrap()
+            Some(quote! {
+                Ok(static_response(
+                    crate::#(#mod_path)::*::CONTENT_TYPE,
+                    crate::#(#mod_path)::*::BODY,
+                ))
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Dispatch the request to the handler exported for its method.
+    ///
+    /// If the module only exports the `handler` function, every request is
+    /// passed to it. Otherwise `HEAD` falls back to `get` (dropping the body),
+    /// `OPTIONS` is answered with the allowed methods and any other method
+    /// without a dedicated function is passed to `handler`, if exported,
+    /// or answered with `405 Method not allowed`.
+    fn api_handler(&self, mod_path: &[Ident]) -> TokenStream {
+        let fallback = format_ident!("{FALLBACK_HANDLER}");
+        let methods = METHOD_HANDLERS
+            .iter()
+            .filter(|(f, _)| self.exports(f))
+            .map(|(f, v)| {
+                (
+                    format_ident!("{f}"),
+                    format_ident!("{v}"),
+                    self.handler_args(f),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let fallback_args = self.handler_args(FALLBACK_HANDLER);
+
+        if methods.is_empty() {
+            return quote! { into_result(crate::#(#mod_path)::*::#fallback(#fallback_args).await) };
+        }
+
+        let allowed = METHOD_HANDLERS
+            .iter()
+            .filter(|(f, _)| {
+                self.exports(f) || (*f == "head" && self.exports("get")) || *f == "options"
+            })
+            .map(|(_, v)| format_ident!("{v}"))
+            .collect::<Vec<_>>();
+        let allowed = quote! { &[#(ReqMethod::#allowed),*] };
+
+        let mut arms = methods
+            .iter()
+            .map(
+                |(f, v, args)| quote! { ReqMethod::#v => into_result(crate::#(#mod_path)::*::#f(#args).await), },
             )
+            .collect::<Vec<_>>();
+
+        if self.exports("get") && !self.exports("head") {
+            let args = self.handler_args("get");
+
+            // hyper drops the body of the responses to `HEAD` requests,
+            // keeping their `Content-Length`
+            arms.push(quote! {
+                ReqMethod::Head => into_result(crate::#(#mod_path)::*::get(#args).await),
+            });
+        }
+
+        if self.exports(FALLBACK_HANDLER) {
+            arms.push(quote! { _ => into_result(crate::#(#mod_path)::*::#fallback(#fallback_args).await), });
         } else {
-            mod_str += ";";
+            if !self.exports("options") {
+                arms.push(quote! { ReqMethod::Options => Ok(options_response(#allowed)), });
+            }
+            arms.push(quote! { ReqMethod::Other(_) => Err(not_implemented()), });
+            arms.push(quote! { _ => Err(method_not_allowed(#allowed)), });
         }
 
-        mod_str
+        quote! { match req.method { #(#arms)* } }
     }
 
-    pub fn get_handlers(&self) -> Vec<String> {
-        let mut handlers: Vec<String> = vec![];
+    pub fn get_mod(&self) -> TokenStream {
+        let name = self.mod_name();
+        let path = &self.path;
 
         if let Some(children) = &self.children {
-            handlers.append(
-                &mut children
-                    .iter()
-                    .flat_map(|c| c.get_handlers())
-                    .collect::<Vec<_>>(),
-            );
-        } else if let Some(handler) = self.handler() {
-            handlers.push(handler);
+            let sub_mods = children.iter().map(|c| c.get_mod());
+
+            quote! {
+                pub mod #name {
+                    #(#sub_mods)*
+                }
+            }
+        } else if self.is_static() {
+            let content_type = match &self.mime_type {
+                Some(mime_type) => {
+                    let mime_type = mime_type.get().trim();
+                    quote! { Some(#mime_type) }
+                }
+                None => quote! { None },
+            };
+
+            quote! {
+                pub mod #name {
+                    pub static CONTENT_TYPE: Option<&str> = #content_type;
+                    pub static BODY: &[u8] = include_bytes!(#path);
+                }
+            }
+        } else {
+            quote! {
+                #[path = #path]
+                pub mod #name;
+            }
         }
+    }
+
+    /// Get the routes served by the server (API and static files),
+    /// with the middleware modules wrapping them, outermost first
+    pub fn get_served(&self) -> Vec<(&Self, Vec<&Self>)> {
+        self.get_served_with(&[])
+    }
 
-        handlers.sort_by(|h1, h2| {
-            let h1_dyn = h1.match_indices("__").count();
-            let h2_dyn = h2.match_indices("__").count();
+    /// Get the served routes, paired with the middleware modules wrapping them
+    /// (the enclosing `middlewares` and those of the nested directories)
+    fn get_served_with<'a>(&'a self, middlewares: &[&'a Self]) -> Vec<(&'a Self, Vec<&'a Self>)> {
+        match &self.children {
+            Some(children) => {
+                let mut middlewares = middlewares.to_vec();
+                middlewares.extend(children.iter().filter(|c| c.is_middleware));
 
-            if h1_dyn == h2_dyn {
-                Ordering::Equal
-            } else if h1_dyn > h2_dyn {
-                Ordering::Greater
-            } else {
-                Ordering::Less
+                children
+                    .iter()
+                    .flat_map(|c| c.get_served_with(&middlewares))
+                    .collect()
             }
-        });
+            None if self.error_page.is_some() => vec![],
+            None if self.is_api() || self.is_static() => vec![(self, middlewares.to_vec())],
+            None => vec![],
+        }
+    }
 
-        handlers
+    /// Get the error pages defined in the `routes` tree
+    pub fn get_error_pages(&self) -> Vec<&Self> {
+        match &self.children {
+            Some(children) => children.iter().flat_map(|c| c.get_error_pages()).collect(),
+            None if self.error_page.is_some() => vec![self],
+            None => vec![],
+        }
     }
 }
@@ -0,0 +1,646 @@
+use super::{
+    config::Config,
+    route::{ErrorPage, Params, Route},
+};
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use std::collections::{BTreeMap, HashSet};
+use syn::{ext::IdentExt, Error};
+
+/// Prefix of the dynamic route segments, matching a single path segment
+static DYNAMIC_PREFIX: &str = "__";
+
+/// Prefix of the catch-all route segments, matching the rest of the path
+static CATCH_ALL_PREFIX: &str = "___";
+
+/// Prefix of the optional catch-all route segments,
+/// matching the rest of the path (if any)
+static OPTIONAL_CATCH_ALL_PREFIX: &str = "____";
+
+/// Types of the typed dynamic route segments (e.g. `__id_u64`),
+/// in matching precedence order
+static SEGMENT_TYPES: [&str; 16] = [
+    "bool", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize",
+    "f32", "f64", "char",
+];
+
+/// A segment of a route path
+#[derive(Debug)]
+enum Segment<'a> {
+    Static(&'a str),
+
+    /// A dynamic segment, matching only the values parsing as its type (if any)
+    Dynamic(&'a str, Option<usize>),
+    CatchAll(&'a str),
+    OptionalCatchAll(&'a str),
+}
+
+impl<'a> Segment<'a> {
+    fn parse(segment: &'a str) -> Self {
+        if let Some(name) = segment.strip_prefix(OPTIONAL_CATCH_ALL_PREFIX) {
+            Segment::OptionalCatchAll(name)
+        } else if let Some(name) = segment.strip_prefix(CATCH_ALL_PREFIX) {
+            Segment::CatchAll(name)
+        } else if let Some(name) = segment.strip_prefix(DYNAMIC_PREFIX) {
+            let typed = name.rsplit_once('_').and_then(|(field, ty)| {
+                let ty = SEGMENT_TYPES.iter().position(|t| *t == ty)?;
+                Some(Segment::Dynamic(field, Some(ty)))
+            });
+
+            typed.unwrap_or(Segment::Dynamic(name, None))
+        } else {
+            Segment::Static(segment)
+        }
+    }
+
+    /// Matching precedence of the segment, lowest first
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Static(_) => 0,
+            Segment::Dynamic(..) => 1,
+            Segment::CatchAll(_) | Segment::OptionalCatchAll(_) => 2,
+        }
+    }
+
+    /// Name of the field storing the value of a non-static segment
+    fn field_name(&self) -> Option<&'a str> {
+        match self {
+            Segment::Static(_) => None,
+            Segment::Dynamic(name, _)
+            | Segment::CatchAll(name)
+            | Segment::OptionalCatchAll(name) => Some(name),
+        }
+    }
+
+    fn is_catch_all(&self) -> bool {
+        matches!(self, Segment::CatchAll(_) | Segment::OptionalCatchAll(_))
+    }
+
+    /// Segment as shown in the error messages, non-static ones without their name
+    fn pattern(&self) -> String {
+        match self {
+            Segment::Static(name) => name.to_string(),
+            Segment::Dynamic(_, None) => format!("{DYNAMIC_PREFIX}*"),
+            Segment::Dynamic(_, Some(ty)) => format!("{DYNAMIC_PREFIX}*_{}", SEGMENT_TYPES[*ty]),
+            Segment::CatchAll(_) => format!("{CATCH_ALL_PREFIX}*"),
+            Segment::OptionalCatchAll(_) => format!("{OPTIONAL_CATCH_ALL_PREFIX}*"),
+        }
+    }
+}
+
+/// A route matching the same paths as a previously inserted one
+struct Conflict {
+    route: usize,
+    other: usize,
+    pattern: String,
+}
+
+/// A node of the routes trie, matching a path segment
+#[derive(Default)]
+struct TrieNode {
+    route: Option<usize>,
+    statics: BTreeMap<String, TrieNode>,
+
+    /// Children matching dynamic segments, by index of their type in
+    /// `SEGMENT_TYPES` (the untyped one being the last)
+    dynamic: BTreeMap<usize, TrieNode>,
+    catch_all: Option<usize>,
+}
+
+impl TrieNode {
+    /// Insert the `route` matching the path `segments`,
+    /// reporting the routes matching the same paths in `conflicts`
+    fn insert(
+        &mut self,
+        segments: &[Segment],
+        route: usize,
+        pattern: String,
+        conflicts: &mut Vec<Conflict>,
+    ) {
+        let mut occupy = |slot: &mut Option<usize>, pattern: String| match slot {
+            Some(other) => conflicts.push(Conflict {
+                route,
+                other: *other,
+                pattern: if pattern.is_empty() {
+                    String::from("/")
+                } else {
+                    pattern
+                },
+            }),
+            None => *slot = Some(route),
+        };
+
+        let Some((segment, rest)) = segments.split_first() else {
+            return occupy(&mut self.route, pattern);
+        };
+        let segment_pattern = format!("{pattern}/{}", segment.pattern());
+
+        match segment {
+            Segment::Static(name) => self.statics.entry(name.to_string()).or_default().insert(
+                rest,
+                route,
+                segment_pattern,
+                conflicts,
+            ),
+            Segment::Dynamic(_, ty) => self
+                .dynamic
+                .entry(ty.unwrap_or(SEGMENT_TYPES.len()))
+                .or_default()
+                .insert(rest, route, segment_pattern, conflicts),
+            Segment::CatchAll(_) => occupy(&mut self.catch_all, segment_pattern),
+            Segment::OptionalCatchAll(_) => {
+                occupy(&mut self.route, pattern);
+                occupy(&mut self.catch_all, segment_pattern);
+            }
+        }
+    }
+
+    /// Generate the `RouteNode` matching the same paths
+    fn to_tokens(&self) -> TokenStream {
+        let option = |route: Option<usize>| match route {
+            Some(route) => quote! { Some(#route) },
+            None => quote! { None },
+        };
+
+        let route = option(self.route);
+        let names = self.statics.keys();
+        let nodes = self.statics.values().map(|n| n.to_tokens());
+        let dynamic = self.dynamic.iter().map(|(ty, node)| {
+            let node = node.to_tokens();
+
+            match SEGMENT_TYPES.get(*ty) {
+                Some(ty) => {
+                    let ty = format_ident!("{ty}");
+                    quote! { (Some(parses::<#ty> as fn(&str) -> bool), #node) }
+                }
+                None => quote! { (None, #node) },
+            }
+        });
+        let catch_all = option(self.catch_all);
+
+        quote! {
+            RouteNode {
+                route: #route,
+                statics: &[#((#names, #nodes)),*],
+                dynamic: &[#(#dynamic),*],
+                catch_all: #catch_all,
+            }
+        }
+    }
+}
+
+/// A route served by the server
+struct RouteEntry<'a> {
+    route: &'a Route,
+    route_path: String,
+
+    /// Middleware modules wrapping the route, outermost first
+    middlewares: Vec<&'a Route>,
+}
+
+impl RouteEntry<'_> {
+    fn segments(&self) -> Vec<Segment<'_>> {
+        self.route_path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(Segment::parse)
+            .collect()
+    }
+}
+
+/// The routes served by the server, sorted by matching precedence:
+/// at the first segment where two routes differ, a static segment takes
+/// precedence over a dynamic one, which takes precedence over a catch-all one.
+pub struct RouteTable<'a> {
+    entries: Vec<RouteEntry<'a>>,
+    error_pages: Vec<&'a Route>,
+}
+
+impl<'a> RouteTable<'a> {
+    pub fn new(base_route: &'a Route) -> Self {
+        let mut entries = base_route
+            .get_served()
+            .into_iter()
+            .map(|(route, middlewares)| RouteEntry {
+                route_path: route.route_path(),
+                route,
+                middlewares,
+            })
+            .collect::<Vec<_>>();
+
+        entries.sort_by_key(|e| e.segments().iter().map(|s| s.rank()).collect::<Vec<_>>());
+
+        // Innermost directories first
+        let mut error_pages = base_route.get_error_pages();
+        error_pages.sort_by_key(|p| (std::cmp::Reverse(Self::scope(p).len()), p.error_page()));
+
+        RouteTable {
+            entries,
+            error_pages,
+        }
+    }
+
+    /// Build the routes trie, reporting the routes matching the same paths
+    fn trie(&self) -> (TrieNode, Vec<Conflict>) {
+        let mut root = TrieNode::default();
+        let mut conflicts = vec![];
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            root.insert(&entry.segments(), i, String::new(), &mut conflicts);
+        }
+
+        (root, conflicts)
+    }
+
+    /// Check that every path is matched by a single route, that catch-all
+    /// segments are the last ones, that the non-static segments of each
+    /// route have distinct names and that they match its `Params` type.
+    pub fn check(&self, config: &Config) -> syn::Result<()> {
+        let mut errors: Option<Error> = None;
+        let mut push_error = |message: String| {
+            let error = Error::new(config.span(), message);
+
+            match &mut errors {
+                Some(errors) => errors.combine(error),
+                None => errors = Some(error),
+            }
+        };
+
+        for entry in &self.entries {
+            let segments = entry.segments();
+            let mut names = HashSet::new();
+
+            for (i, segment) in segments.iter().enumerate() {
+                if segment.is_catch_all() && i < segments.len() - 1 {
+                    push_error(format!(
+                        "Route {} has a catch-all segment before the last one",
+                        entry.route.path()
+                    ));
+                }
+
+                if let Some(name) = segment.field_name() {
+                    if !names.insert(name) {
+                        push_error(format!(
+                            "Route {} uses the segment name `{name}` more than once",
+                            entry.route.path()
+                        ));
+                    }
+                }
+            }
+
+            let fields = segments.iter().filter_map(|s| s.field_name()).count();
+
+            match entry.route.params() {
+                Some(Params::Struct(params)) => {
+                    let params = params
+                        .iter()
+                        .map(|p| p.unraw().to_string())
+                        .collect::<HashSet<_>>();
+
+                    if params.len() != names.len() || names.iter().any(|n| !params.contains(*n)) {
+                        let mut names = names.into_iter().collect::<Vec<_>>();
+                        names.sort();
+
+                        push_error(format!(
+                            "Route {} must declare the `Params` fields {}",
+                            entry.route.path(),
+                            names
+                                .iter()
+                                .map(|n| format!("`{n}`"))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ));
+                    }
+                }
+                Some(Params::TupleStruct(count) | Params::Tuple(count)) if *count != fields => {
+                    push_error(format!(
+                        "Route {} declares {count} `Params` instead of {fields}",
+                        entry.route.path()
+                    ));
+                }
+                Some(Params::Single) if fields != 1 => push_error(format!(
+                    "Route {} declares a single `Params` instead of {fields}",
+                    entry.route.path()
+                )),
+                None if !entry.route.params_handlers().is_empty() => push_error(format!(
+                    "Route {} has handlers taking parameters but no `Params` type",
+                    entry.route.path()
+                )),
+                _ => {}
+            }
+        }
+
+        for conflict in self.trie().1 {
+            push_error(format!(
+                "Conflicting routes: {} and {} both match `{}`",
+                self.entries[conflict.other].route.path(),
+                self.entries[conflict.route].route.path(),
+                conflict.pattern
+            ));
+        }
+
+        match errors {
+            Some(errors) => Err(errors),
+            None => Ok(()),
+        }
+    }
+
+    /// Generate the `ROUTER` trie, resolving a path to the index of its route
+    pub fn router(&self) -> TokenStream {
+        let root = self.trie().0.to_tokens();
+
+        quote! {
+            static ROUTER: RouteNode = #root;
+        }
+    }
+
+    /// Get the match arms handling the requests for each route index,
+    /// setting the values of the non-static segments on the request,
+    /// parsing them as the `Params` of the route (if any) and passing
+    /// the request through the middlewares of the route
+    pub fn handlers(&self) -> Vec<TokenStream> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| {
+                let handler = entry.route.handler()?;
+                let segments = entry.segments();
+                let names = segments
+                    .iter()
+                    .filter(|s| matches!(s, Segment::Dynamic(..)))
+                    .filter_map(|s| s.field_name())
+                    .collect::<Vec<_>>();
+                let catch_all = segments
+                    .iter()
+                    .filter(|s| s.is_catch_all())
+                    .filter_map(|s| s.field_name())
+                    .next();
+
+                // The params are parsed inside the middlewares chain, so that
+                // their errors go through the middlewares too
+                let handler = match Self::params(entry.route, &names, catch_all) {
+                    Some(params) => quote! { async move { #params #handler }.await },
+                    None => handler,
+                };
+                let handler = Self::wrap(entry, handler);
+
+                if names.is_empty() && catch_all.is_none() {
+                    return Some(quote! { Some(#i) => #handler, });
+                }
+
+                let catch_all = match catch_all {
+                    Some(name) => quote! { Some(#name) },
+                    None => quote! { None },
+                };
+
+                // The params are parsed from an owned copy of the values,
+                // moved into the middlewares chain
+                let owned_values = entry.route.params().map(|_| {
+                    quote! { let values = values.iter().map(|v| v.to_string()).collect::<Vec<_>>(); }
+                });
+
+                Some(quote! {
+                    Some(#i) => {
+                        req.dyn_fields = Some(dyn_fields(&[#(#names),*], #catch_all, &values));
+                        #owned_values
+                        #handler
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Segments of the directory of an error page, matching the paths in its
+    /// scope (`None` for the non-static ones)
+    fn scope(page: &Route) -> Vec<Option<String>> {
+        let route_path = page.route_path();
+        let mut segments = route_path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| match Segment::parse(s) {
+                Segment::Static(name) => Some(name.to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        segments.pop();
+        segments
+    }
+
+    /// Generate `render_error_page`, rendering an error with the error page
+    /// of the innermost directory containing the request path (if any)
+    pub fn error_pages(&self) -> TokenStream {
+        let has_error_pages = !self.error_pages.is_empty();
+        let pages = self.error_pages.iter().map(|page| {
+            let scope = Self::scope(page).into_iter().map(|s| match s {
+                Some(name) => quote! { Some(#name) },
+                None => quote! { None },
+            });
+            let renders = match page.error_page() {
+                Some(ErrorPage::NotFound) => quote! { code == 404 },
+                Some(ErrorPage::ServerError) => quote! { code >= 500 },
+                _ => quote! { true },
+            };
+            let html = page.is_html().then(|| quote! { && html });
+            let renderer = page.error_renderer();
+
+            quote! {
+                if #renders #html && in_scope(&segments, &[#(#scope),*]) {
+                    return #renderer;
+                }
+            }
+        });
+
+        // The HTML pages are only rendered for the clients preferring HTML,
+        // unless the handler has already answered with an HTML body
+        let html = self.error_pages.iter().any(|page| page.is_html()).then(|| {
+            quote! {
+                let html = prefers_html(error.headers.accept())
+                    && !error.response.headers.content_type().is_some_and(|t| t.starts_with("text/html"));
+            }
+        });
+
+        quote! {
+            /// Whether the `routes` tree defines error pages
+            const HAS_ERROR_PAGES: bool = #has_error_pages;
+
+            async fn render_error_page(error: ErrorInfo) -> Response {
+                let code = error.response.code;
+                let segments = path_segments(error.path_only());
+                #html
+
+                #(#pages)*
+
+                error.response
+            }
+        }
+    }
+
+    /// Wrap the `handler` of the `entry` in its middlewares, each one
+    /// receiving the rest of the chain as `Next`
+    fn wrap(entry: &RouteEntry, handler: TokenStream) -> TokenStream {
+        let mut arg = if entry.route.is_static() {
+            quote! { _ }
+        } else {
+            quote! { req }
+        };
+
+        entry
+            .middlewares
+            .iter()
+            .rev()
+            .fold(handler, |handler, middleware| {
+                let middleware = middleware.middleware();
+                let handler = quote! {
+                    #middleware(req, Next::new(move |#arg| async move { #handler })).await
+                };
+
+                arg = quote! { req };
+                handler
+            })
+    }
+
+    /// Generate the statement parsing the values of the dynamic segments `names`
+    /// and of the `catch_all` segment as the `Params` of the `route` (if any)
+    fn params(route: &Route, names: &[&str], catch_all: Option<&str>) -> Option<TokenStream> {
+        let params = route.params()?;
+        let ty = route.params_type();
+        let mut values = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (*name, quote! { parse_param(#name, &values[#i])? }))
+            .collect::<Vec<_>>();
+
+        if let Some(name) = catch_all {
+            let rest = names.len();
+            values.push((name, quote! { parse_params(#name, &values[#rest..])? }));
+        }
+
+        let value = match params {
+            Params::Struct(fields) => {
+                let fields = fields.iter().filter_map(|field| {
+                    let (_, value) = values.iter().find(|(name, _)| field.unraw() == name)?;
+                    Some(quote! { #field: #value })
+                });
+
+                quote! { #ty { #(#fields),* } }
+            }
+            Params::TupleStruct(_) => {
+                let values = values.iter().map(|(_, value)| value);
+                quote! { #ty(#(#values),*) }
+            }
+            Params::Tuple(_) => {
+                let values = values.iter().map(|(_, value)| value);
+                quote! { (#(#values,)*) }
+            }
+            Params::Single => values.first()?.1.clone(),
+        };
+
+        let binding = if route.params_handlers().is_empty() {
+            quote! { _ }
+        } else {
+            quote! { params }
+        };
+
+        Some(quote! { let #binding: #ty = #value; })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    static HANDLER: &str =
+        "pub async fn get(_req: Request<'_>) -> Result<Response, Response> { todo!() }";
+
+    /// Check the routes tree made of the `files` (relative path and content),
+    /// returning the error messages (without the tree path)
+    fn check(name: &str, files: &[(&str, &str)]) -> Vec<String> {
+        let dir = std::env::temp_dir().join(format!("next_rs_{name}_{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+
+        for (path, content) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        let dir_path = dir.to_string_lossy().to_string();
+        let config = Config::default();
+        let result = Route::new(dir_path.clone(), String::new(), &config)
+            .and_then(|route| RouteTable::new(&route).check(&config));
+        fs::remove_dir_all(&dir).ok();
+
+        result.err().map_or(vec![], |errors| {
+            errors
+                .into_iter()
+                .map(|e| e.to_string().replace(&dir_path, ""))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn dynamic_segments_in_same_directory() {
+        assert_eq!(
+            check("dynamic", &[("__id.rs", HANDLER), ("__slug.rs", HANDLER)]),
+            ["Conflicting routes: /__id.rs and /__slug.rs both match `/__*`"]
+        );
+    }
+
+    #[test]
+    fn typed_segments_of_same_type() {
+        assert_eq!(
+            check(
+                "typed",
+                &[
+                    ("__id_u64.rs", HANDLER),
+                    ("__num_u64.rs", HANDLER),
+                    ("__name.rs", HANDLER)
+                ]
+            ),
+            ["Conflicting routes: /__id_u64.rs and /__num_u64.rs both match `/__*_u64`"]
+        );
+    }
+
+    #[test]
+    fn file_and_mod_rs() {
+        assert_eq!(
+            check("mod_rs", &[("foo.rs", HANDLER), ("foo/mod.rs", HANDLER)]),
+            ["Conflicting routes: /foo and /foo.rs both define the module `foo`"]
+        );
+    }
+
+    #[test]
+    fn index_html_and_mod_rs() {
+        assert_eq!(
+            check(
+                "index",
+                &[
+                    ("docs/index.html", "<html></html>"),
+                    ("docs/mod.rs", HANDLER)
+                ]
+            ),
+            ["Conflicting routes: /docs/index.html and /docs/mod.rs both match `/docs`"]
+        );
+    }
+
+    #[test]
+    fn catch_all_before_last_segment() {
+        assert_eq!(
+            check("catch_all", &[("___rest/page.rs", HANDLER)]),
+            ["Route /___rest/page.rs has a catch-all segment before the last one"]
+        );
+    }
+
+    #[test]
+    fn optional_catch_all_and_index() {
+        assert_eq!(
+            check(
+                "optional",
+                &[("index.html", "<html></html>"), ("____path.rs", HANDLER)]
+            ),
+            ["Conflicting routes: /index.html and /____path.rs both match `/`"]
+        );
+    }
+}
@@ -0,0 +1,6 @@
+use crate::{ErrorInfo, Response};
+
+pub async fn render(error: ErrorInfo) -> Response {
+    let code = error.response.code;
+    Response::builder(code).text(format!("API error {code} on {}", error.path))
+}
@@ -0,0 +1,5 @@
+use crate::{ErrorInfo, Response};
+
+pub async fn render(error: ErrorInfo) -> Response {
+    panic!("Can't render the error of {}", error.path)
+}
@@ -0,0 +1,6 @@
+use crate::{Request, Response};
+
+pub async fn get<'a>(req: Request<'a>) -> Result<Response, Response> {
+    let path = req.dyn_fields.as_ref().unwrap()["path"].as_list();
+    Ok(Response::ok().text(format!("docs {path:?}")))
+}
@@ -0,0 +1,6 @@
+use crate::{Request, Response};
+
+pub async fn post<'a>(mut req: Request<'a>) -> Result<Response, Response> {
+    let text = req.body.text().await?;
+    Ok(Response::ok().text(text))
+}
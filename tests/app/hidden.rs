@@ -0,0 +1,5 @@
+use crate::{Request, Response};
+
+pub async fn get<'a>(_: Request<'a>) -> Result<Response, Response> {
+    Err(Response::not_found().html("<p>Hidden</p>"))
+}
@@ -0,0 +1,6 @@
+use crate::{Request, Response};
+
+pub async fn get<'a>(req: Request<'a>) -> Result<Response, Response> {
+    let rest = &req.dyn_fields.as_ref().unwrap()["rest"];
+    Ok(Response::ok().text(format!("catch-all {rest}")))
+}
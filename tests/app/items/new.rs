@@ -0,0 +1,5 @@
+use crate::{Request, Response};
+
+pub async fn get<'a>(_: Request<'a>) -> Result<Response, Response> {
+    Ok(Response::ok().text("static"))
+}
@@ -0,0 +1,6 @@
+use crate::{Request, Response};
+
+pub async fn get<'a>(req: Request<'a>) -> Result<Response, Response> {
+    let id = &req.dyn_fields.as_ref().unwrap()["id"];
+    Ok(Response::ok().text(format!("order {id}")))
+}
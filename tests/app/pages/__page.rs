@@ -0,0 +1,7 @@
+use crate::{Request, Response};
+
+pub type Params = u16;
+
+pub async fn get<'a>(_: Request<'a>, page: Params) -> Result<Response, Response> {
+    Ok(Response::ok().text(format!("page {page}")))
+}
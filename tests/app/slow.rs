@@ -0,0 +1,7 @@
+use crate::{Request, Response};
+use std::time::Duration;
+
+pub async fn get<'a>(_: Request<'a>) -> Result<Response, Response> {
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    Ok(Response::ok().text("Done"))
+}
@@ -0,0 +1,7 @@
+use crate::{Request, Response};
+
+pub type Params = Vec<i32>;
+
+pub async fn get<'a>(_: Request<'a>, values: Params) -> Result<Response, Response> {
+    Ok(Response::ok().text(values.iter().sum::<i32>().to_string()))
+}
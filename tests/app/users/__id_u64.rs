@@ -0,0 +1,7 @@
+use crate::{Request, Response};
+
+pub type Params = u64;
+
+pub async fn get<'a>(_: Request<'a>, id: Params) -> Result<Response, Response> {
+    Ok(Response::ok().text(format!("user {id}")))
+}
@@ -0,0 +1,95 @@
+use super::{send_body, start};
+use std::net::SocketAddr;
+
+/// Post the `body` with the `content_type` to the echo route
+async fn echo(address: SocketAddr, content_type: &str, body: &[u8]) -> (u16, String) {
+    let headers = [&*format!("Content-Type: {content_type}")];
+    let response = send_body(address, "POST", "/echo", &headers, body).await;
+    (response.status, response.body)
+}
+
+#[tokio::test]
+async fn text_charsets() {
+    let address = start(|server| server);
+    let ok = |text: &str| (200, text.to_string());
+
+    assert_eq!(
+        echo(address, "text/plain", "café".as_bytes()).await,
+        ok("café")
+    );
+    assert_eq!(
+        echo(address, "text/plain; charset=UTF-8", "café".as_bytes()).await,
+        ok("café")
+    );
+    assert_eq!(
+        echo(address, r#"text/plain; Charset="iso-8859-1""#, b"caf\xE9").await,
+        ok("café")
+    );
+    assert_eq!(
+        echo(address, "text/plain; charset=utf-16le", b"c\0a\0f\0\xE9\0").await,
+        ok("café")
+    );
+    assert_eq!(
+        echo(address, "text/plain; charset=utf-16", b"\0c\0a\0f\0\xE9").await,
+        ok("café")
+    );
+}
+
+#[tokio::test]
+async fn text_byte_order_marks() {
+    let address = start(|server| server);
+    let ok = |text: &str| (200, text.to_string());
+
+    // The byte order mark takes precedence over the `Content-Type` charset
+    assert_eq!(
+        echo(
+            address,
+            "text/plain; charset=latin1",
+            b"\xEF\xBB\xBFcaf\xC3\xA9"
+        )
+        .await,
+        ok("café")
+    );
+    assert_eq!(
+        echo(address, "text/plain", b"\xFF\xFEc\0a\0f\0\xE9\0").await,
+        ok("café")
+    );
+    assert_eq!(
+        echo(address, "text/plain", b"\xFE\xFF\0c\0a\0f\0\xE9").await,
+        ok("café")
+    );
+}
+
+#[tokio::test]
+async fn invalid_text() {
+    let address = start(|server| server);
+
+    assert_eq!(
+        echo(address, "text/plain", b"caf\xE9").await,
+        (
+            400,
+            r#"{"message":"Request body is not valid utf-8"}"#.to_string()
+        )
+    );
+    assert_eq!(
+        echo(address, "text/plain; charset=utf-16le", b"c\0a").await,
+        (
+            400,
+            r#"{"message":"Request body is not valid utf-16le"}"#.to_string()
+        )
+    );
+    assert_eq!(
+        echo(address, "text/plain; charset=utf-16be", b"\xD8\0\0a").await,
+        (
+            400,
+            r#"{"message":"Request body is not valid utf-16be"}"#.to_string()
+        )
+    );
+    assert_eq!(
+        echo(address, "text/plain; charset=shift_jis", b"abc").await,
+        (
+            415,
+            r#"{"message":"Unsupported charset `shift_jis`"}"#.to_string()
+        )
+    );
+}
@@ -0,0 +1,174 @@
+use super::{send, start, CorsPolicy, WebServer};
+use std::{io, net::SocketAddr};
+
+fn policy() -> CorsPolicy {
+    CorsPolicy::new()
+        .allow_origin("https://app.example.com/")
+        .allow_origin("https://*.example.org")
+        .allow_origin_fn(|origin| origin.starts_with("http://localhost:"))
+        .allow_credentials(true)
+        .expose_headers(&["X-Request-Id"])
+}
+
+/// The `Access-Control-Allow-Origin` header of the response to a request from `origin`
+async fn allowed_origin(address: SocketAddr, origin: &str) -> Option<String> {
+    let response = send(address, "GET", "/hello", &[&format!("Origin: {origin}")]).await;
+    response
+        .header("access-control-allow-origin")
+        .map(String::from)
+}
+
+#[tokio::test]
+async fn origins() {
+    let address = start(|server| server.with_cors(policy()));
+
+    for origin in [
+        "https://app.example.com",
+        "https://api.example.org",
+        "https://a.b.example.org",
+        "http://localhost:3000",
+    ] {
+        assert_eq!(
+            allowed_origin(address, origin).await.as_deref(),
+            Some(origin)
+        );
+    }
+
+    for origin in [
+        "http://app.example.com",
+        "https://app.example.com.evil.com",
+        "https://example.org",
+        "https://.example.org",
+        "http://api.example.org",
+        "https://evil.com/.example.org",
+        "https://evilexample.org",
+    ] {
+        assert_eq!(allowed_origin(address, origin).await, None, "{origin}");
+    }
+}
+
+#[tokio::test]
+async fn response_headers() {
+    let address = start(|server| server.with_cors(policy()));
+
+    let response = send(
+        address,
+        "GET",
+        "/hello",
+        &["Origin: https://app.example.com"],
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("access-control-allow-credentials"),
+        Some("true")
+    );
+    assert_eq!(
+        response.header("access-control-expose-headers"),
+        Some("X-Request-Id")
+    );
+    assert_eq!(response.header_values("vary"), ["Origin"]);
+
+    let response = send(address, "GET", "/hello", &["Origin: https://evil.com"]).await;
+    assert_eq!(response.header("access-control-allow-origin"), None);
+
+    let address = start(|server| server.with_cors(CorsPolicy::permissive()));
+
+    let response = send(address, "GET", "/hello", &["Origin: https://evil.com"]).await;
+    assert_eq!(response.header("access-control-allow-origin"), Some("*"));
+    assert_eq!(response.header("access-control-allow-credentials"), None);
+    assert!(response.header_values("vary").is_empty());
+}
+
+#[tokio::test]
+async fn preflight() {
+    let address = start(|server| server.with_cors(policy().allow_methods(&["get", "post"])));
+    let preflight = |origin: &'static str| async move {
+        let headers = [
+            &*format!("Origin: {origin}"),
+            "Access-Control-Request-Method: POST",
+            "Access-Control-Request-Headers: content-type, x-token",
+        ];
+        send(address, "OPTIONS", "/hello", &headers).await
+    };
+
+    let response = preflight("https://api.example.org").await;
+    assert_eq!(response.status, 204);
+    assert_eq!(
+        response.header("access-control-allow-origin"),
+        Some("https://api.example.org")
+    );
+    assert_eq!(
+        response.header("access-control-allow-methods"),
+        Some("GET, POST")
+    );
+    assert_eq!(
+        response.header("access-control-allow-headers"),
+        Some("content-type, x-token")
+    );
+    assert_eq!(
+        response.header_values("vary"),
+        ["Origin", "Access-Control-Request-Headers"]
+    );
+
+    let response = preflight("https://evil.com").await;
+    assert_eq!(response.status, 204);
+    assert_eq!(response.header("access-control-allow-origin"), None);
+    assert_eq!(response.header("access-control-allow-methods"), None);
+
+    let address = start(|server| {
+        server.with_cors(
+            policy()
+                .allow_headers(&["Content-Type"])
+                .max_age(std::time::Duration::from_secs(600)),
+        )
+    });
+
+    let headers = [
+        "Origin: https://app.example.com",
+        "Access-Control-Request-Method: GET",
+        "Access-Control-Request-Headers: x-token",
+    ];
+    let response = send(address, "OPTIONS", "/hello", &headers).await;
+    assert_eq!(
+        response.header("access-control-allow-headers"),
+        Some("Content-Type")
+    );
+    assert_eq!(response.header("access-control-max-age"), Some("600"));
+    assert_eq!(response.header_values("vary"), ["Origin"]);
+}
+
+#[tokio::test]
+async fn invalid_policies() {
+    for (policy, message) in [
+        (
+            CorsPolicy::permissive().allow_credentials(true),
+            "CORS credentials can't be allowed for any origin",
+        ),
+        (
+            CorsPolicy::new().allow_credentials(true).allow_origin("*"),
+            "CORS credentials can't be allowed for any origin",
+        ),
+        (
+            CorsPolicy::new().allow_origin("*.example.com"),
+            "CORS origin pattern `*.example.com` has no scheme (e.g. `https://*.example.com`)",
+        ),
+    ] {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = WebServer::bind([])
+            .with_listener(listener)
+            .with_cors(policy);
+
+        let error = server.start().await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(error.to_string(), message);
+    }
+
+    // Credentials are only refused while any origin is allowed
+    let policy = CorsPolicy::permissive()
+        .allow_credentials(true)
+        .allow_credentials(false);
+    let address = start(|server| server.with_cors(policy));
+    let response = send(address, "GET", "/hello", &["Origin: https://evil.com"]).await;
+    assert_eq!(response.status, 200);
+}
@@ -0,0 +1,76 @@
+use super::{get, send, start};
+use std::net::SocketAddr;
+
+/// Whether the static `_404.html` page answers a request for a missing path
+/// sent with the `accept` header
+async fn renders_html(address: SocketAddr, accept: &str) -> bool {
+    let response = send(address, "GET", "/missing", &[&format!("Accept: {accept}")]).await;
+    assert_eq!(response.status, 404);
+    response.body == "<h1>Not found</h1>\n"
+}
+
+#[tokio::test]
+async fn static_pages_for_html_clients() {
+    let address = start(|server| server);
+
+    assert_eq!(
+        get(address, "/missing").await.body,
+        r#"{"message":"Not found"}"#
+    );
+
+    for accept in [
+        "text/html",
+        "TEXT/HTML",
+        "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+        "text/html, application/json;q=0.9",
+        "text/*, application/json;q=0.5",
+        "application/json;q=0.1, text/html;q=0.2",
+    ] {
+        assert!(renders_html(address, accept).await, "{accept}");
+    }
+
+    for accept in [
+        "application/json",
+        "*/*",
+        "text/html;q=0.5, application/json",
+        "text/html;q=0, */*",
+        "text/*;q=0.5, text/html;q=0.1, */*;q=0.3",
+        "image/png",
+    ] {
+        assert!(!renders_html(address, accept).await, "{accept}");
+    }
+
+    // HTML error bodies of the handlers are kept
+    let response = send(address, "GET", "/hidden", &["Accept: text/html"]).await;
+    assert_eq!(response.status, 404);
+    assert_eq!(response.body, "<p>Hidden</p>");
+}
+
+#[tokio::test]
+async fn rendered_pages_for_any_client() {
+    let address = start(|server| server);
+
+    for headers in [
+        &[][..],
+        &["Accept: application/json"],
+        &["Accept: text/html"],
+    ] {
+        let response = send(address, "GET", "/api/missing?page=2", headers).await;
+        assert_eq!(response.status, 404);
+        assert_eq!(response.body, "API error 404 on /api/missing?page=2");
+    }
+}
+
+#[tokio::test]
+async fn panicking_render() {
+    let address = start(|server| server);
+
+    let response = get(address, "/api/panic/missing").await;
+    assert_eq!(response.status, 500);
+
+    let request_id = response.header("x-request-id").unwrap();
+    assert_eq!(
+        response.body,
+        format!(r#"{{"message":"Internal server error","request_id":"{request_id}"}}"#)
+    );
+}
@@ -0,0 +1,13 @@
+use super::{get, start};
+use std::time::Duration;
+
+#[tokio::test]
+async fn request_timeout() {
+    let address = start(|server| server.with_request_timeout(Duration::from_millis(50)));
+
+    let response = get(address, "/slow").await;
+    assert_eq!(response.status, 504);
+    assert_eq!(response.body, r#"{"message":"Request timed out"}"#);
+
+    assert_eq!(get(address, "/hello").await.status, 200);
+}
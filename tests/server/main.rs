@@ -0,0 +1,133 @@
+//! Requests sent to the server generated from the `tests/app` routes
+
+use next_rs::make_server;
+use std::net::SocketAddr;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+make_server!("tests/app");
+
+mod body;
+mod cors;
+mod errors;
+mod limits;
+mod methods;
+mod query;
+mod router;
+mod socket_io;
+mod sse;
+
+/// The parts of a response read by `send`
+struct RawResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl RawResponse {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn header_values(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+}
+
+/// Start `server` on a free local port
+fn start(server: impl FnOnce(WebServer) -> WebServer) -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = server(WebServer::bind([]).with_listener(listener));
+
+    tokio::spawn(async move { server.start().await });
+
+    address
+}
+
+/// Send a `method` request for `path` with the `headers` lines and read the whole response
+async fn send(address: SocketAddr, method: &str, path: &str, headers: &[&str]) -> RawResponse {
+    send_body(address, method, path, headers, b"").await
+}
+
+/// Send a `method` request for `path` with the `headers` lines and the `body`,
+/// and read the whole response
+async fn send_body(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &[&str],
+    body: &[u8],
+) -> RawResponse {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let mut request =
+        format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n");
+
+    for header in headers {
+        request.push_str(header);
+        request.push_str("\r\n");
+    }
+
+    if !body.is_empty() {
+        request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+    stream.write_all(body).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap().split(' ').nth(1).unwrap();
+    let headers: Vec<_> = lines
+        .filter_map(|line| line.split_once(": "))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    let chunked = headers
+        .iter()
+        .any(|(key, value)| key.eq_ignore_ascii_case("transfer-encoding") && value == "chunked");
+
+    RawResponse {
+        status: status.parse().unwrap(),
+        body: if chunked {
+            dechunk(body)
+        } else {
+            body.to_string()
+        },
+        headers,
+    }
+}
+
+async fn get(address: SocketAddr, path: &str) -> RawResponse {
+    send(address, "GET", path, &[]).await
+}
+
+/// Join the chunks of a `Transfer-Encoding: chunked` body
+fn dechunk(mut body: &str) -> String {
+    let mut joined = String::new();
+
+    while let Some((size, rest)) = body.split_once("\r\n") {
+        let size = usize::from_str_radix(size, 16).unwrap();
+
+        if size == 0 {
+            break;
+        }
+
+        joined.push_str(&rest[..size]);
+        body = &rest[size + 2..];
+    }
+
+    joined
+}
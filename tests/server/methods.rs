@@ -0,0 +1,15 @@
+use super::{get, send, start};
+
+#[tokio::test]
+async fn head_keeps_content_length() {
+    let address = start(|server| server);
+
+    let response = get(address, "/hello").await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "Hello");
+
+    let response = send(address, "HEAD", "/hello", &[]).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-length"), Some("5"));
+    assert_eq!(response.body, "");
+}
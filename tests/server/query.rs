@@ -0,0 +1,53 @@
+use super::QueryParams;
+use std::collections::HashMap;
+
+#[test]
+fn decoding() {
+    let params = QueryParams::parse("q=rust+web%20server&path=%2Fdocs%2Fa%26b&emoji=%F0%9F%A6%80");
+    assert_eq!(params.get("q"), Some("rust web server"));
+    assert_eq!(params.get("path"), Some("/docs/a&b"));
+    assert_eq!(params.get("emoji"), Some("🦀"));
+
+    // Encoded keys, literal `+` and invalid escapes
+    let params = QueryParams::parse("first%20name=Ada&sum=1%2B1&bad=100%&odd=%zz");
+    assert_eq!(params.get("first name"), Some("Ada"));
+    assert_eq!(params.get("sum"), Some("1+1"));
+    assert_eq!(params.get("bad"), Some("100%"));
+    assert_eq!(params.get("odd"), Some("%zz"));
+}
+
+#[test]
+fn repeated_keys() {
+    let params = QueryParams::parse("id=1&sort=asc&id=2&id=3");
+    assert_eq!(params.get("id"), Some("1"));
+    assert_eq!(params.get_all("id"), ["1", "2", "3"]);
+    assert_eq!(params.get_all("page"), Vec::<&str>::new());
+    assert_eq!(params.len(), 4);
+    assert_eq!(
+        params.iter().collect::<Vec<_>>(),
+        [("id", "1"), ("sort", "asc"), ("id", "2"), ("id", "3")]
+    );
+    assert_eq!(
+        params.to_multimap(),
+        HashMap::from([
+            (
+                String::from("id"),
+                vec![String::from("1"), String::from("2"), String::from("3")]
+            ),
+            (String::from("sort"), vec![String::from("asc")]),
+        ])
+    );
+}
+
+#[test]
+fn keys_without_value() {
+    let params = QueryParams::parse("debug&verbose=&lang=en&&");
+    assert!(params.contains("debug"));
+    assert_eq!(params.get("debug"), Some(""));
+    assert_eq!(params.get("verbose"), Some(""));
+    assert_eq!(params.get("lang"), Some("en"));
+    assert!(!params.contains("missing"));
+    assert_eq!(params.len(), 3);
+
+    assert!(QueryParams::parse("").is_empty());
+}
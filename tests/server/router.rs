@@ -0,0 +1,72 @@
+use super::{get, start};
+
+#[tokio::test]
+async fn static_then_dynamic_then_catch_all() {
+    let address = start(|server| server);
+
+    assert_eq!(get(address, "/items/new").await.body, "static");
+    assert_eq!(get(address, "/items/7").await.body, "dynamic 7");
+    assert_eq!(get(address, "/items/7/edit").await.body, "edit 7");
+    assert_eq!(get(address, "/items/a/b/c").await.body, "catch-all a/b/c");
+    assert_eq!(get(address, "/items").await.status, 404);
+}
+
+#[tokio::test]
+async fn backtracking() {
+    let address = start(|server| server);
+
+    // The static `new` route has no `edit` child, matched by the dynamic subtree
+    assert_eq!(get(address, "/items/new/edit").await.body, "edit new");
+
+    // The dynamic subtree has no `other` child, matched by the catch-all
+    assert_eq!(
+        get(address, "/items/7/other").await.body,
+        "catch-all 7/other"
+    );
+}
+
+#[tokio::test]
+async fn optional_catch_all() {
+    let address = start(|server| server);
+
+    assert_eq!(get(address, "/docs").await.body, "docs []");
+    assert_eq!(get(address, "/docs/").await.body, "docs []");
+    assert_eq!(
+        get(address, "/docs/guide/intro.md").await.body,
+        r#"docs ["guide", "intro.md"]"#
+    );
+    assert_eq!(
+        get(address, "/docs/a%2Fb/c%20d").await.body,
+        r#"docs ["a/b", "c d"]"#
+    );
+}
+
+#[tokio::test]
+async fn typed_segments() {
+    let address = start(|server| server);
+
+    assert_eq!(get(address, "/users/7").await.body, "user 7");
+    assert_eq!(get(address, "/users/bob").await.body, "name bob");
+    assert_eq!(get(address, "/users/-7").await.body, "name -7");
+
+    assert_eq!(get(address, "/orders/42").await.body, "order 42");
+    assert_eq!(get(address, "/orders/abc").await.status, 404);
+    assert_eq!(get(address, "/orders/4294967296").await.status, 404);
+}
+
+#[tokio::test]
+async fn params_not_parsing() {
+    let address = start(|server| server);
+
+    assert_eq!(get(address, "/pages/3").await.body, "page 3");
+
+    let response = get(address, "/pages/abc").await;
+    assert_eq!(response.status, 400);
+    assert_eq!(
+        response.body,
+        r#"{"message":"Invalid route parameter `page`: invalid digit found in string"}"#
+    );
+
+    assert_eq!(get(address, "/sum/1/2/3").await.body, "6");
+    assert_eq!(get(address, "/sum/1/x").await.status, 400);
+}
@@ -0,0 +1,22 @@
+use super::{get, send, start};
+
+#[tokio::test]
+async fn socket_io_path() {
+    let address = start(|server| server);
+
+    let response = get(address, "/socket.io/?EIO=4&transport=polling").await;
+    assert_eq!(response.status, 200);
+    assert!(
+        response.body.starts_with(r#"0{"sid":"#),
+        "{}",
+        response.body
+    );
+
+    // Upgrade requests to the other paths are passed to the routes
+    let headers = ["Upgrade: websocket", "Connection: Upgrade"];
+    let response = send(address, "GET", "/hello", &headers).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "Hello");
+
+    assert_eq!(get(address, "/ws").await.status, 404);
+}
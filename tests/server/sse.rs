@@ -0,0 +1,62 @@
+use super::{Response, ResponseBody, SseEvent};
+use futures::{stream, StreamExt};
+use serde_json::json;
+use std::time::Duration;
+
+/// The chunks of the `Response::sse` body sending the `events`
+async fn chunks(events: Vec<SseEvent>) -> Vec<String> {
+    let ResponseBody::Stream(body) = Response::sse(stream::iter(events), None).body else {
+        panic!("SSE bodies are streamed");
+    };
+
+    body.map(|chunk| String::from_utf8(chunk.unwrap().to_vec()).unwrap())
+        .collect()
+        .await
+}
+
+#[tokio::test]
+async fn data_lines() {
+    assert_eq!(
+        chunks(vec![
+            SseEvent::new("Hello"),
+            SseEvent::new("first\nsecond\r\nthird"),
+            SseEvent::new("trailing\n"),
+            SseEvent::new(""),
+            SseEvent::json(&json!({"progress": 50})),
+        ])
+        .await,
+        [
+            "data: Hello\n\n",
+            "data: first\ndata: second\ndata: third\n\n",
+            "data: trailing\ndata: \n\n",
+            "data: \n\n",
+            "data: {\"progress\":50}\n\n",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn fields() {
+    assert_eq!(
+        chunks(vec![SseEvent::new("done")
+            .id(42)
+            .event("status\r\nupdate")
+            .retry(Duration::from_millis(1500))])
+        .await,
+        ["event: statusupdate\nid: 42\nretry: 1500\ndata: done\n\n"]
+    );
+}
+
+#[tokio::test]
+async fn keep_alive() {
+    let events = stream::pending::<SseEvent>();
+    let response = Response::sse(events, Some(Duration::from_millis(10)));
+    assert_eq!(response.headers.content_type(), Some("text/event-stream"));
+    assert_eq!(response.headers.get("cache-control"), Some("no-cache"));
+
+    let ResponseBody::Stream(mut body) = response.body else {
+        panic!("SSE bodies are streamed");
+    };
+    let chunk = body.next().await.unwrap().unwrap();
+    assert_eq!(&chunk[..], b": keep-alive\n\n");
+}
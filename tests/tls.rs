@@ -0,0 +1,222 @@
+//! HTTPS requests sent to the server generated with `tls = true`
+
+use next_rs::make_server;
+use std::{fs, io, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{
+        self,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+        pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime},
+        DigitallySignedStruct, SignatureScheme,
+    },
+    TlsConnector,
+};
+
+make_server!("tests/app", tls = true);
+
+/// Path of a PEM file of the `tests/fixtures` directory
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// The certificate of the `name.pem` fixture
+fn certificate(name: &str) -> CertificateDer<'static> {
+    CertificateDer::from_pem_file(fixture(&format!("{name}.pem"))).unwrap()
+}
+
+/// Accept any server certificate, the tests checking which one is served
+#[derive(Debug)]
+struct AcceptAnyCert(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _: &CertificateDer<'_>,
+        _: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        _: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Start a server on a free local port, serving HTTPS with `tls`
+fn start(tls: TlsConfig) -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = WebServer::bind([])
+        .with_listener(listener)
+        .with_tls_config(tls);
+
+    tokio::spawn(async move { server.start().await });
+
+    address
+}
+
+/// Send a request for `/hello` to `server_name`, returning the certificate
+/// served by the server and the response
+async fn get_hello(address: SocketAddr, server_name: &str) -> (CertificateDer<'static>, String) {
+    let provider = Arc::new(ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCert(provider)))
+        .with_no_client_auth();
+
+    let stream = TcpStream::connect(address).await.unwrap();
+    let server_name = ServerName::try_from(server_name.to_string()).unwrap();
+    let mut stream = TlsConnector::from(Arc::new(config))
+        .connect(server_name, stream)
+        .await
+        .unwrap();
+    let certificate = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+
+    let request = "GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = vec![];
+    // The server closes the connection without a TLS `close_notify`
+    stream.read_to_end(&mut response).await.ok();
+
+    (certificate, String::from_utf8(response).unwrap())
+}
+
+#[test]
+fn pem_files() {
+    assert!(TlsConfig::new(fixture("first.pem"), fixture("first.key")).is_ok());
+
+    let error = TlsConfig::new(fixture("missing.pem"), fixture("first.key"))
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("missing.pem"), "{error}");
+
+    // The private key is checked against the certificate
+    let error = TlsConfig::new(fixture("first.pem"), fixture("second.key"))
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("second.key"), "{error}");
+
+    let error = TlsConfig::new(fixture("first.key"), fixture("first.pem"))
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn https() {
+    let tls = TlsConfig::new(fixture("first.pem"), fixture("first.key")).unwrap();
+    let address = start(tls);
+
+    let (served, response) = get_hello(address, "localhost").await;
+    assert_eq!(served, certificate("first"));
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nHello"), "{response}");
+}
+
+#[tokio::test]
+async fn sni() {
+    let tls = TlsConfig::new(fixture("first.pem"), fixture("first.key"))
+        .unwrap()
+        .with_sni(
+            "Second.localhost",
+            fixture("second.pem"),
+            fixture("second.key"),
+        )
+        .unwrap();
+    let address = start(tls);
+
+    assert_eq!(
+        get_hello(address, "localhost").await.0,
+        certificate("first")
+    );
+    assert_eq!(
+        get_hello(address, "second.localhost").await.0,
+        certificate("second")
+    );
+}
+
+#[tokio::test]
+async fn reload() {
+    let dir = std::env::temp_dir().join(format!("next_rs_tls_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("cert.key"));
+    fs::copy(fixture("first.pem"), &cert_path).unwrap();
+    fs::copy(fixture("first.key"), &key_path).unwrap();
+
+    let tls = TlsConfig::new(&cert_path, &key_path)
+        .unwrap()
+        .with_reload(Duration::from_millis(10));
+    let address = start(tls);
+    assert_eq!(
+        get_hello(address, "localhost").await.0,
+        certificate("first")
+    );
+
+    // An invalid pair is ignored, keeping the current certificate
+    fs::write(&key_path, "").unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(
+        get_hello(address, "localhost").await.0,
+        certificate("first")
+    );
+
+    fs::copy(fixture("second.pem"), &cert_path).unwrap();
+    fs::copy(fixture("second.key"), &key_path).unwrap();
+
+    let mut served = certificate("first");
+
+    for _ in 0..100 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        served = get_hello(address, "localhost").await.0;
+
+        if served != certificate("first") {
+            break;
+        }
+    }
+
+    fs::remove_dir_all(&dir).ok();
+    assert_eq!(served, certificate("second"));
+}